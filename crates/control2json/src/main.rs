@@ -5,7 +5,8 @@ use std::{
 };
 
 use clap::Parser;
-use control_file::ControlFile;
+use control_file::{infrastructure::Infrastructure, map::MapEntry, ControlFile};
+use serde::Serialize;
 
 use self::error::Control2JsonError;
 
@@ -20,6 +21,20 @@ pub mod cli {
         #[clap(long, value_parser)]
         /// The path to a json map.
         map: Option<String>,
+        /// Report the matched, unmatched and not applicable dependencies as json.
+        #[clap(long, value_parser)]
+        report: bool,
+        /// Leave debian infrastructure (`debhelper`, `dh-*`), that is not applicable
+        /// to nix, out of the dependencies.
+        /// Implied by `--report`, `--ignore` and `--unignore`.
+        #[clap(long, value_parser)]
+        skip_infrastructure: bool,
+        /// Additional debian infrastructure patterns (`dh-*`), that are not applicable to nix.
+        #[clap(long, value_parser, value_delimiter = ',')]
+        ignore: Vec<String>,
+        /// Patterns, that are removed from the curated debian infrastructure.
+        #[clap(long, value_parser, value_delimiter = ',')]
+        unignore: Vec<String>,
     }
 
     impl CliArgs {
@@ -30,6 +45,25 @@ pub mod cli {
         pub(crate) fn input(&self) -> &str {
             self.input.as_ref()
         }
+
+        pub(crate) fn report(&self) -> bool {
+            self.report
+        }
+
+        pub(crate) fn skip_infrastructure(&self) -> bool {
+            self.skip_infrastructure
+                || self.report
+                || !self.ignore.is_empty()
+                || !self.unignore.is_empty()
+        }
+
+        pub(crate) fn ignore(&self) -> &[String] {
+            self.ignore.as_ref()
+        }
+
+        pub(crate) fn unignore(&self) -> &[String] {
+            self.unignore.as_ref()
+        }
     }
 }
 
@@ -45,6 +79,8 @@ mod error {
         Utf8(#[from] std::str::Utf8Error),
         #[error("Control File Error {0}")]
         ControlFile(#[from] control_file::ControlFileError),
+        #[error("Serde Error {0}")]
+        Serde(#[from] serde_json::Error),
     }
 }
 
@@ -60,10 +96,18 @@ fn main() -> Result<(), Control2JsonError> {
     let mut buffer = Vec::new();
     reader.read_to_end(&mut buffer)?;
     let pkgs = pkgs_from_control_file(std::str::from_utf8(&buffer)?)?;
+    let (pkgs, not_applicable) = if opts.skip_infrastructure() {
+        Infrastructure::with_overrides(opts.ignore(), opts.unignore()).partition(pkgs)
+    } else {
+        (pkgs, vec![])
+    };
     let mut stdout = io::stdout();
 
-    if let Some(location) = opts.map() {
-        let map = get_map(location)?;
+    let map = opts.map().map(|location| get_map(location)).transpose()?;
+    if opts.report() {
+        let report = report_from_map(pkgs, not_applicable, map.unwrap_or_default());
+        stdout.write_all(serde_json::to_string(&report)?.as_bytes())?;
+    } else if let Some(map) = map {
         let result = match_from_map(pkgs, map)?;
         let fmt = format!("{:?}", result);
         stdout.write_all(fmt.as_bytes())?;
@@ -83,26 +127,83 @@ fn pkgs_from_control_file(control_file: &str) -> Result<Vec<String>, Control2Jso
     Ok(deps)
 }
 
-fn get_map(map: &str) -> Result<HashMap<String, String>, Control2JsonError> {
-    let mut file = fs::File::open(&map)?;
+fn get_map(map: &str) -> Result<HashMap<String, MapEntry>, Control2JsonError> {
+    let mut file = fs::File::open(map)?;
     let mut buffer = String::new();
     file.read_to_string(&mut buffer)?;
-    let map: HashMap<String, String> = serde_json::from_str(&buffer).unwrap();
+    let map: HashMap<String, MapEntry> = serde_json::from_str(&buffer)?;
     Ok(map)
 }
 
 fn match_from_map(
     control: Vec<String>,
-    map: HashMap<String, String>,
+    map: HashMap<String, MapEntry>,
 ) -> Result<Vec<String>, Control2JsonError> {
     let mut result = vec![];
 
     for pkg in control {
         if let Some(matched) = map.get(&pkg) {
-            result.push(matched.into());
+            result.extend(matched.attr_paths());
         }
     }
     result.sort();
     result.dedup();
     Ok(result)
 }
+
+/// The dependencies of a control file, split by how they relate to nix.
+#[derive(Debug, Serialize)]
+struct Report {
+    /// Dependencies, that have an entry in the map.
+    matched: Vec<String>,
+    /// The attribute paths, that the matched dependencies map to.
+    targets: Vec<String>,
+    /// Dependencies, that have no entry in the map.
+    unmatched: Vec<String>,
+    /// Debian infrastructure, that has no nix equivalent.
+    not_applicable: Vec<String>,
+}
+
+fn report_from_map(
+    control: Vec<String>,
+    not_applicable: Vec<String>,
+    map: HashMap<String, MapEntry>,
+) -> Report {
+    let (matched, unmatched): (Vec<String>, Vec<String>) =
+        control.into_iter().partition(|pkg| map.contains_key(pkg));
+    let mut targets = matched
+        .iter()
+        .filter_map(|pkg| map.get(pkg))
+        .flat_map(MapEntry::attr_paths)
+        .collect::<Vec<String>>();
+    targets.sort();
+    targets.dedup();
+    Report {
+        matched,
+        targets,
+        unmatched,
+        not_applicable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lists_debian_names() {
+        let map: HashMap<String, MapEntry> = serde_json::from_str(
+            r#"{
+                "libxcb1-dev": [{ "attr": "xorg.libxcb", "output": "dev" }],
+                "zlib1g-dev": "zlib.dev"
+            }"#,
+        )
+        .unwrap();
+        let control = ["libxcb1-dev", "libfoo-dev", "zlib1g-dev"].map(String::from);
+        let report = report_from_map(control.to_vec(), vec!["debhelper".into()], map);
+        assert_eq!(report.matched, ["libxcb1-dev", "zlib1g-dev"]);
+        assert_eq!(report.targets, ["xorg.libxcb.dev", "zlib.dev"]);
+        assert_eq!(report.unmatched, ["libfoo-dev"]);
+        assert_eq!(report.not_applicable, ["debhelper"]);
+    }
+}
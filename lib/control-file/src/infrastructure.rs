@@ -0,0 +1,168 @@
+//! Classification of debian packages, that are only part of debian's own
+//! packaging infrastructure and have no equivalent in nixpkgs.
+
+/// The curated default patterns, a trailing `*` matches any suffix.
+const DEFAULT_PATTERNS: &[&str] = &[
+    "${*}",
+    "adduser",
+    "build-essential",
+    "cdbs",
+    "dctrl-tools",
+    "debconf",
+    "debconf-2.0",
+    "debhelper",
+    "debhelper-compat",
+    "devscripts",
+    "dh-*",
+    "dpkg",
+    "dpkg-dev",
+    "fakeroot",
+    "init-system-helpers",
+    "lsb-base",
+    "po-debconf",
+    "quilt",
+    "ucf",
+];
+
+/// Decides, which debian packages are not applicable to nix.
+#[derive(Debug, Clone)]
+pub struct Infrastructure {
+    patterns: Vec<String>,
+    /// Packages, that are applicable even though a pattern matches them.
+    exceptions: Vec<String>,
+}
+
+impl Default for Infrastructure {
+    fn default() -> Self {
+        Self {
+            patterns: DEFAULT_PATTERNS.iter().map(|s| String::from(*s)).collect(),
+            exceptions: vec![],
+        }
+    }
+}
+
+impl Infrastructure {
+    /// Adds patterns to, and removes patterns from the curated defaults.
+    /// An unignored pattern, that is not a pattern itself (`dh-python` for `dh-*`),
+    /// is kept as an exception.
+    pub fn with_overrides<I, R>(ignore: I, unignore: R) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+        R: IntoIterator,
+        R::Item: AsRef<str>,
+    {
+        let mut infrastructure = Self::default();
+        infrastructure
+            .patterns
+            .extend(ignore.into_iter().map(Into::into));
+        for pattern in unignore {
+            let pattern = pattern.as_ref();
+            let before = infrastructure.patterns.len();
+            infrastructure
+                .patterns
+                .retain(|existing| existing != pattern);
+            if infrastructure.patterns.len() == before {
+                infrastructure.exceptions.push(pattern.into());
+            }
+        }
+        infrastructure
+    }
+
+    /// Checks if a debian package is part of the packaging infrastructure.
+    pub fn is_infrastructure(&self, pkg: &str) -> bool {
+        self.patterns.iter().any(|pattern| glob_match(pattern, pkg))
+            && !self
+                .exceptions
+                .iter()
+                .any(|exception| glob_match(exception, pkg))
+    }
+
+    /// Splits packages into the applicable ones and the infrastructure.
+    pub fn partition(&self, pkgs: Vec<String>) -> (Vec<String>, Vec<String>) {
+        pkgs.into_iter()
+            .partition(|pkg| !self.is_infrastructure(pkg))
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        self.patterns.as_ref()
+    }
+}
+
+/// Matches a pattern, where `*` matches any sequence of characters.
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(first) = parts.next() else {
+        return pattern == value;
+    };
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<&str>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkgs(pkgs: &[&str]) -> Vec<String> {
+        pkgs.iter().map(|pkg| String::from(*pkg)).collect()
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("debhelper", "debhelper"));
+        assert!(!glob_match("debhelper", "debhelper-compat"));
+        assert!(glob_match("dh-*", "dh-python"));
+        assert!(glob_match("dh-*", "dh-"));
+        assert!(!glob_match("dh-*", "dh"));
+        assert!(!glob_match("dh-*", "libdh-foo"));
+        assert!(glob_match("${*}", "${misc:Depends}"));
+        assert!(!glob_match("${*}", "${misc:Depends"));
+        assert!(glob_match("*-dev", "libxcb1-dev"));
+        assert!(glob_match("lib*-*-dev", "libgtk-3-dev"));
+        assert!(!glob_match("lib*-*-dev", "libxcb1-dev"));
+        // the suffix can't overlap with the prefix
+        assert!(!glob_match("ab*ba", "aba"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn partition_with_defaults() {
+        let (applicable, infrastructure) = Infrastructure::default().partition(pkgs(&[
+            "debhelper-compat",
+            "libxcb1-dev",
+            "dh-python",
+            "zlib1g-dev",
+            "fakeroot",
+        ]));
+        assert_eq!(applicable, ["libxcb1-dev", "zlib1g-dev"]);
+        assert_eq!(
+            infrastructure,
+            ["debhelper-compat", "dh-python", "fakeroot"]
+        );
+    }
+
+    #[test]
+    fn partition_with_overrides() {
+        let infrastructure =
+            Infrastructure::with_overrides(["xvfb", "xauth*"], ["fakeroot", "dh-python"]);
+        let (applicable, ignored) =
+            infrastructure.partition(pkgs(&["xvfb", "xauth", "fakeroot", "dh-python", "dh-exec"]));
+        assert_eq!(applicable, ["fakeroot", "dh-python"]);
+        assert_eq!(ignored, ["xvfb", "xauth", "dh-exec"]);
+        // removed patterns are not kept as exceptions
+        assert!(!infrastructure.patterns().contains(&"fakeroot".into()));
+        assert!(infrastructure.patterns().contains(&"dh-*".into()));
+    }
+}
@@ -1,3 +1,8 @@
+/// Debian packaging infrastructure, that is not applicable to nix.
+pub mod infrastructure;
+/// The targets, that debian packages map to in nixpkgs.
+pub mod map;
+
 use debcontrol::Paragraph;
 use log::debug;
 use thiserror::Error;
@@ -17,6 +22,7 @@ pub struct ControlFile<'a> {
 }
 
 impl<'a, 'b> ControlFile<'b> {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(content: &'b str) -> Result<ControlFile<'a>, ControlFileError>
     where
         'b: 'a,
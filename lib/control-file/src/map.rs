@@ -0,0 +1,145 @@
+//! The entries of a map from debian packages to nix attribute outputs: `{deb-lib: [target]}`,
+//! shared by debnix, which generates the maps, and the tools that consume them.
+use serde::{Deserialize, Serialize};
+
+/// Output names, that can be split off the end of an attribute path.
+pub const NIX_OUTPUTS: &[&str] = &[
+    "out", "dev", "lib", "bin", "doc", "devdoc", "man", "info", "static", "debug",
+];
+
+/// A nix attribute output, that a debian pkg maps to.
+/// A debian pkg can map to several targets, if it is split up in nixpkgs,
+/// and several debian pkgs can map to different outputs of the same attribute.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NixTarget {
+    attr: String,
+    /// The output of the attribute, the default output is left implicit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    /// The confidence of the match, unknown for maps in the flat format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confidence: Option<f64>,
+}
+
+impl NixTarget {
+    pub fn new(attr: String, output: Option<String>, confidence: Option<f64>) -> Self {
+        Self {
+            attr,
+            output,
+            confidence,
+        }
+    }
+
+    /// Splits a known output off of an attribute path: `xorg.libxcb.dev`.
+    pub fn from_attr_path(attr_path: &str, confidence: Option<f64>) -> Self {
+        match attr_path.rsplit_once('.') {
+            Some((attr, output)) if NIX_OUTPUTS.contains(&output) => Self {
+                attr: attr.into(),
+                output: (output != "out").then(|| output.into()),
+                confidence,
+            },
+            _ => Self::new(attr_path.into(), None, confidence),
+        }
+    }
+
+    pub fn attr(&self) -> &str {
+        self.attr.as_ref()
+    }
+
+    pub fn output(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
+    pub fn confidence(&self) -> Option<f64> {
+        self.confidence
+    }
+
+    /// The attribute path of the output, as used in the flat format: `xorg.libxcb.dev`.
+    pub fn attr_path(&self) -> String {
+        match &self.output {
+            Some(output) => format!("{}.{output}", self.attr),
+            None => self.attr.clone(),
+        }
+    }
+
+    pub fn is_same_output(&self, other: &NixTarget) -> bool {
+        self.attr == other.attr && self.output == other.output
+    }
+
+    /// Keeps the higher of both confidences.
+    pub fn raise_confidence(&mut self, confidence: Option<f64>) {
+        if confidence > self.confidence {
+            self.confidence = confidence;
+        }
+    }
+}
+
+/// The targets of a single debian pkg in a map,
+/// either a list of targets, or a single attribute path in the former flat format.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MapEntry {
+    Flat(String),
+    Targets(Vec<NixTarget>),
+}
+
+impl MapEntry {
+    /// The attribute paths of the targets: `xorg.libxcb.dev`.
+    pub fn attr_paths(&self) -> Vec<String> {
+        match self {
+            MapEntry::Flat(attr_path) => vec![attr_path.clone()],
+            MapEntry::Targets(targets) => targets.iter().map(NixTarget::attr_path).collect(),
+        }
+    }
+}
+
+impl From<MapEntry> for Vec<NixTarget> {
+    fn from(entry: MapEntry) -> Self {
+        match entry {
+            MapEntry::Flat(attr_path) => vec![NixTarget::from_attr_path(&attr_path, None)],
+            MapEntry::Targets(targets) => targets,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_from_attr_paths() {
+        let target = NixTarget::from_attr_path("xorg.libxcb.dev", Some(0.9));
+        assert_eq!(target.attr(), "xorg.libxcb");
+        assert_eq!(target.output(), Some("dev"));
+        assert_eq!(target.attr_path(), "xorg.libxcb.dev");
+        let target = NixTarget::from_attr_path("zlib.out", None);
+        assert_eq!((target.attr(), target.output()), ("zlib", None));
+        let target = NixTarget::from_attr_path("qt5.qtbase", None);
+        assert_eq!((target.attr(), target.output()), ("qt5.qtbase", None));
+    }
+
+    #[test]
+    fn raise_confidence() {
+        let mut target = NixTarget::from_attr_path("zlib", Some(0.5));
+        target.raise_confidence(None);
+        target.raise_confidence(Some(0.4));
+        assert_eq!(target.confidence(), Some(0.5));
+        target.raise_confidence(Some(0.8));
+        assert_eq!(target.confidence(), Some(0.8));
+    }
+
+    #[test]
+    fn entries_in_both_formats() {
+        let flat: MapEntry = serde_json::from_str(r#""xorg.libxcb.dev""#).unwrap();
+        let targets: MapEntry = serde_json::from_str(
+            r#"[{ "attr": "xorg.libxcb", "output": "dev" }, { "attr": "zlib", "confidence": 0.5 }]"#,
+        )
+        .unwrap();
+        assert_eq!(flat.attr_paths(), ["xorg.libxcb.dev"]);
+        assert_eq!(targets.attr_paths(), ["xorg.libxcb.dev", "zlib"]);
+        let flat: Vec<NixTarget> = flat.into();
+        let targets: Vec<NixTarget> = targets.into();
+        assert!(flat[0].is_same_output(&targets[0]));
+        assert!(!flat[0].is_same_output(&targets[1]));
+    }
+}
@@ -0,0 +1,305 @@
+//! Aggregating the matches of many discovered pkgs into a single map.
+//! Every output votes for the targets of the debian pkgs it matched,
+//! disagreements are resolved through a policy and reported for review.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::error::DebNixError;
+use crate::matcher::NixTarget;
+
+/// How the target of a debian pkg is chosen, when the outputs disagree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// The targets with the most votes.
+    #[default]
+    Majority,
+    /// The targets with the highest confidence.
+    Confidence,
+    /// The targets of the curated map, the majority for pkgs it doesn't contain.
+    /// Pkgs, that only the curated map contains, are part of the result as well.
+    Curated,
+}
+
+/// A target of a debian pkg, together with the outputs that voted for it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    /// The target with the highest confidence of its votes.
+    target: NixTarget,
+    votes: usize,
+    sources: Vec<String>,
+}
+
+impl Candidate {
+    /// Targets without a recorded confidence are assumed to be certain.
+    fn confidence(&self) -> f64 {
+        self.target.confidence().unwrap_or(1.0)
+    }
+
+    fn sorted_sources(&self) -> Vec<&String> {
+        let mut sources = self.sources.iter().collect::<Vec<_>>();
+        sources.sort();
+        sources
+    }
+}
+
+/// The candidates of a debian pkg, whose outputs disagreed.
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    /// The attribute paths, that the conflict was resolved to.
+    resolved: Vec<String>,
+    /// All candidates, in the order of the policy.
+    candidates: Vec<Candidate>,
+}
+
+/// `<map>-conflicts.json`, the disagreeing entries of a generated map.
+#[derive(Debug, Serialize)]
+pub struct ConflictReport {
+    policy: ConflictPolicy,
+    conflicts: BTreeMap<String, Conflict>,
+}
+
+/// The votes of all outputs: `{deb-lib: [candidate]}`.
+#[derive(Debug, Default)]
+pub struct Aggregate {
+    candidates: BTreeMap<String, Vec<Candidate>>,
+}
+
+impl Aggregate {
+    /// Records the vote of a source for a target, every source votes once per target.
+    pub fn vote(&mut self, deb: &str, target: NixTarget, source: &str) {
+        let candidates = self.candidates.entry(deb.into()).or_default();
+        match candidates
+            .iter_mut()
+            .find(|candidate| candidate.target.is_same_output(&target))
+        {
+            Some(candidate) => {
+                candidate.target.raise_confidence(target.confidence());
+                if !candidate.sources.iter().any(|known| known == source) {
+                    candidate.votes += 1;
+                    candidate.sources.push(source.into());
+                }
+            }
+            None => candidates.push(Candidate {
+                target,
+                votes: 1,
+                sources: vec![source.into()],
+            }),
+        }
+    }
+
+    /// Resolves the targets of every debian pkg, independent of the order of the votes.
+    /// Multiple targets of a pkg are kept, and are not a conflict, as long as the outputs
+    /// agree on them. Otherwise a single target wins, ties within the policy are broken
+    /// by the attr path, and the candidates that are left out are reported as a conflict.
+    pub fn resolve(
+        mut self,
+        policy: ConflictPolicy,
+        curated: Option<&BTreeMap<String, Vec<NixTarget>>>,
+    ) -> Result<(BTreeMap<String, Vec<NixTarget>>, ConflictReport), DebNixError> {
+        if policy == ConflictPolicy::Curated && curated.is_none() {
+            return Err(DebNixError::Config(
+                "The curated conflict policy needs a curated map (`--map`).".into(),
+            ));
+        }
+        let mut map = BTreeMap::new();
+        let mut conflicts = BTreeMap::new();
+        for (deb, mut candidates) in std::mem::take(&mut self.candidates) {
+            let by_votes = |a: &Candidate, b: &Candidate| {
+                b.votes
+                    .cmp(&a.votes)
+                    .then_with(|| b.confidence().total_cmp(&a.confidence()))
+            };
+            let by_confidence = |a: &Candidate, b: &Candidate| {
+                b.confidence()
+                    .total_cmp(&a.confidence())
+                    .then_with(|| b.votes.cmp(&a.votes))
+            };
+            let curated_targets = curated
+                .filter(|_| policy == ConflictPolicy::Curated)
+                .and_then(|curated| curated.get(&deb));
+            let resolved = if let Some(targets) = curated_targets {
+                candidates.sort_by(|a, b| {
+                    by_votes(a, b).then_with(|| a.target.attr_path().cmp(&b.target.attr_path()))
+                });
+                targets.clone()
+            } else {
+                let order = if policy == ConflictPolicy::Confidence {
+                    by_confidence
+                } else {
+                    by_votes
+                };
+                candidates.sort_by(|a, b| {
+                    order(a, b).then_with(|| a.target.attr_path().cmp(&b.target.attr_path()))
+                });
+                if agreed(&candidates) {
+                    candidates
+                        .iter()
+                        .map(|candidate| candidate.target.clone())
+                        .collect()
+                } else {
+                    // a tie is won by the first attr path
+                    vec![candidates[0].target.clone()]
+                }
+            };
+            let agreed = agreed(&candidates);
+            let kept = candidates.iter().all(|candidate| {
+                resolved
+                    .iter()
+                    .any(|target| target.is_same_output(&candidate.target))
+            });
+            if !agreed || !kept {
+                conflicts.insert(
+                    deb.clone(),
+                    Conflict {
+                        resolved: resolved.iter().map(NixTarget::attr_path).collect(),
+                        candidates,
+                    },
+                );
+            }
+            map.insert(deb, resolved);
+        }
+        if let Some(curated) = curated.filter(|_| policy == ConflictPolicy::Curated) {
+            for (deb, targets) in curated {
+                map.entry(deb.clone()).or_insert_with(|| targets.clone());
+            }
+        }
+        Ok((map, ConflictReport { policy, conflicts }))
+    }
+}
+
+/// Whether every candidate was voted for by the same outputs.
+fn agreed(candidates: &[Candidate]) -> bool {
+    candidates
+        .iter()
+        .all(|candidate| candidate.sorted_sources() == candidates[0].sorted_sources())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(attr_path: &str, confidence: f64) -> NixTarget {
+        NixTarget::from_attr_path(attr_path, Some(confidence))
+    }
+
+    fn attr_paths(targets: &[NixTarget]) -> Vec<String> {
+        targets.iter().map(NixTarget::attr_path).collect()
+    }
+
+    /// The votes of the sources for the targets of `libfoo-dev`.
+    fn aggregate(votes: &[(&str, &str, f64)]) -> Aggregate {
+        let mut aggregate = Aggregate::default();
+        for (source, attr_path, confidence) in votes {
+            aggregate.vote("libfoo-dev", target(attr_path, *confidence), source);
+        }
+        aggregate
+    }
+
+    #[test]
+    fn majority() {
+        let (map, report) = aggregate(&[
+            ("a", "foo.dev", 0.5),
+            ("b", "foo.dev", 0.5),
+            ("c", "libfoo", 0.9),
+            // a source votes only once per target
+            ("c", "libfoo", 0.9),
+        ])
+        .resolve(ConflictPolicy::Majority, None)
+        .unwrap();
+        assert_eq!(attr_paths(&map["libfoo-dev"]), ["foo.dev"]);
+        let conflict = &report.conflicts["libfoo-dev"];
+        assert_eq!(conflict.resolved, ["foo.dev"]);
+        assert_eq!(conflict.candidates[1].votes, 1);
+    }
+
+    #[test]
+    fn majority_ties_have_a_single_winner() {
+        let (map, report) = aggregate(&[
+            ("d", "libfoo", 0.5),
+            ("a", "foo.dev", 0.5),
+            ("b", "foo.dev", 0.5),
+            ("c", "libfoo", 0.5),
+        ])
+        .resolve(ConflictPolicy::Majority, None)
+        .unwrap();
+        assert_eq!(attr_paths(&map["libfoo-dev"]), ["foo.dev"]);
+        let conflict = &report.conflicts["libfoo-dev"];
+        assert_eq!(conflict.resolved, ["foo.dev"]);
+        assert_eq!(conflict.candidates[1].target.attr_path(), "libfoo");
+    }
+
+    #[test]
+    fn confidence_ordering() {
+        let votes = [
+            ("a", "foo.dev", 0.5),
+            ("b", "foo.dev", 0.6),
+            ("c", "libfoo", 0.9),
+        ];
+        let (map, report) = aggregate(&votes)
+            .resolve(ConflictPolicy::Confidence, None)
+            .unwrap();
+        assert_eq!(attr_paths(&map["libfoo-dev"]), ["libfoo"]);
+        let candidates = &report.conflicts["libfoo-dev"].candidates;
+        assert_eq!(candidates[0].target.attr_path(), "libfoo");
+        // the highest confidence of the votes is kept
+        assert_eq!(candidates[1].target.confidence(), Some(0.6));
+
+        let (map, _) = aggregate(&votes)
+            .resolve(ConflictPolicy::Majority, None)
+            .unwrap();
+        assert_eq!(attr_paths(&map["libfoo-dev"]), ["foo.dev"]);
+    }
+
+    #[test]
+    fn curated_override() {
+        let curated = BTreeMap::from([
+            ("libfoo-dev".to_owned(), vec![target("foo.dev", 1.0)]),
+            ("libbar-dev".to_owned(), vec![target("bar.dev", 1.0)]),
+        ]);
+        let mut aggregate = aggregate(&[("a", "libfoo", 0.9), ("b", "libfoo", 0.9)]);
+        aggregate.vote("libbaz-dev", target("baz.dev", 0.9), "a");
+        let (map, report) = aggregate
+            .resolve(ConflictPolicy::Curated, Some(&curated))
+            .unwrap();
+        assert_eq!(attr_paths(&map["libfoo-dev"]), ["foo.dev"]);
+        assert_eq!(report.conflicts["libfoo-dev"].resolved, ["foo.dev"]);
+        // pkgs, that only the curated map contains
+        assert_eq!(attr_paths(&map["libbar-dev"]), ["bar.dev"]);
+        // pkgs, that the curated map doesn't contain
+        assert_eq!(attr_paths(&map["libbaz-dev"]), ["baz.dev"]);
+        assert!(!report.conflicts.contains_key("libbaz-dev"));
+
+        // the curated map is only used by its policy
+        let (map, _) = Aggregate::default()
+            .resolve(ConflictPolicy::Majority, Some(&curated))
+            .unwrap();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn curated_needs_a_map() {
+        let error = aggregate(&[("a", "libfoo", 0.9)])
+            .resolve(ConflictPolicy::Curated, None)
+            .unwrap_err();
+        assert!(matches!(error, DebNixError::Config(_)));
+    }
+
+    #[test]
+    fn agreed_multi_target_is_not_a_conflict() {
+        let (map, report) = aggregate(&[
+            ("a", "qt5.qtbase.dev", 0.9),
+            ("a", "qt5.qttools.dev", 0.9),
+            ("b", "qt5.qttools.dev", 0.8),
+            ("b", "qt5.qtbase.dev", 0.8),
+        ])
+        .resolve(ConflictPolicy::Majority, None)
+        .unwrap();
+        assert_eq!(
+            attr_paths(&map["libfoo-dev"]),
+            ["qt5.qtbase.dev", "qt5.qttools.dev"]
+        );
+        assert!(report.conflicts.is_empty());
+    }
+}
@@ -1,5 +1,11 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::aggregate::ConflictPolicy;
+use crate::input::DiscoverInput;
+use crate::report::ReportFormat;
+
 #[derive(Parser, Clone)]
 pub(crate) struct CliArgs {
     pkg: Option<String>,
@@ -7,21 +13,120 @@ pub(crate) struct CliArgs {
     discover: Option<usize>,
     #[clap(long, value_parser)]
     discover_start: Option<usize>,
+    /// Where the pkgs for discovery come from, defaults to `popcon:./test/popcon.csv`:
+    /// `popcon:<path-or-url>`, `list:<path>`, `stdin`, `sources:<path>`, `control-dir:<dir>`.
+    /// Without `--discover`, all of its pkgs are discovered.
+    #[clap(long, value_name = "INPUT", value_parser = clap::value_parser!(DiscoverInput))]
+    discover_from: Option<DiscoverInput>,
+    /// Continue the discovery of the pending pkgs in the journal of the output directory.
+    #[clap(long, value_parser)]
+    resume: bool,
+    /// Attempt the pkgs again, that the journal records as failed, implies `--resume`.
+    #[clap(long, value_parser)]
+    retry_failed: bool,
+    /// Discover the pkgs of the output directory again, whose debian control file,
+    /// or nix derivations changed since they were discovered.
+    #[clap(long, value_parser)]
+    refresh: bool,
+    /// Discover the debian dependencies of every discovered pkg as well,
+    /// also of a single `--pkg`, which is then written into the output directory.
+    /// The pkgs are recorded in the journal of the output directory.
+    #[clap(long, value_parser)]
+    recursive: bool,
+    /// How deep the dependency closure of a recursive discovery is followed.
+    #[clap(long, value_parser, default_value_t = 2)]
+    max_depth: usize,
+    /// The amount of pkgs, that are discovered concurrently.
+    #[clap(long, value_parser, default_value_t = 1)]
+    jobs: usize,
+    /// The amount of concurrent requests to debian, defaults to the amount of jobs.
+    #[clap(long, value_parser)]
+    http_jobs: Option<usize>,
+    /// The amount of concurrent `nix` subprocesses, defaults to the amount of jobs.
+    #[clap(long, value_parser)]
+    nix_jobs: Option<usize>,
     /// Set a timeout in minutes after which the program will gracefully exit
     #[clap(long, value_parser)]
     timeout: Option<usize>,
     #[clap(long, value_parser)]
-    /// The location of the generated output files.
+    /// The location of the generated output files: the output file of a single `--pkg`,
+    /// or `--elf`, and the output directory of a sweep (`--discover`, `--discover-from`,
+    /// `--recursive`, `--resume`, `--refresh`, `--retry-failed`), which holds
+    /// a `<pkg>-debnix.json` for every pkg, the error records and the journal.
     output: Option<String>,
     /// Generates completion for the specified shell.
     #[clap(long, value_name = "SHELL", value_parser)]
     generate_completion: Option<String>,
     #[clap(long, value_parser)]
-    /// The map that is generated out of multiple input files.
+    /// The map that is generated out of multiple input files,
+    /// either a file, or a directory for `debnix.json`.
+    /// `<map>-flat.json`, `<map>-nixdeb.json` and `<map>-conflicts.json` are written next to it.
     generate_map: Option<String>,
+    /// How disagreeing outputs are resolved in the generated map,
+    /// `curated` prefers the entries of the input map (`--map`).
+    #[clap(long, value_enum, default_value_t = ConflictPolicy::Majority)]
+    conflict_policy: ConflictPolicy,
+    /// The output directories, that the map is generated from, defaults to `./outputs`.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    map_input: Vec<String>,
+    /// Summarize the error records of an output directory by their kind.
+    #[clap(long, value_name = "OUTPUT", value_parser)]
+    error_summary: Option<String>,
+    /// Report the coverage and the failures of the discovery run in an output directory.
+    #[clap(long, value_name = "OUTPUT", value_parser)]
+    report: Option<String>,
+    /// The format of the report.
+    #[clap(long, value_enum, default_value_t = ReportFormat::Json)]
+    report_format: ReportFormat,
+    /// Leave matches below this confidence (`0.0` - `1.0`) out of the generated map,
+    /// the report counts them as low confidence.
+    #[clap(long, value_parser)]
+    min_confidence: Option<f64>,
+    /// Look up the debian names of a nix attribute (`libxkbcommon`),
+    /// in the discovered outputs and the input map.
+    #[clap(long, value_parser)]
+    nix_attr: Option<String>,
     /// The input map, that can be used for Lookup.
     #[clap(long, value_parser)]
     map: Option<String>,
+    /// A local debian `Contents-<arch>` index, used for file based matching.
+    #[clap(long, value_parser, requires = "nix_files")]
+    contents: Option<String>,
+    /// A nix-index database (`~/.cache/nix-index/files`),
+    /// or a local listing of the files in nixpkgs, used for file based matching.
+    #[clap(long, value_parser, requires = "contents")]
+    nix_files: Option<String>,
+    /// A prebuilt binary or a `.deb`, whose runtime dependencies
+    /// are translated through the sonames it needs.
+    #[clap(long, value_parser, requires = "contents")]
+    elf: Option<String>,
+    /// A debian `shlibs` file, or a directory of them (`/var/lib/dpkg/info`),
+    /// used to resolve sonames to debian packages.
+    #[clap(long, value_parser)]
+    shlibs: Option<String>,
+    /// A local debian `Packages` index, used to resolve virtual packages
+    /// (`awk`, `mail-transport-agent`) through their providers.
+    #[clap(long, value_parser)]
+    packages: Option<String>,
+    /// A json file, that configures the stages and strategies of the matcher.
+    #[clap(long, value_parser)]
+    match_config: Option<String>,
+    /// Disable matching strategies, e.g. the lossy `strip-lib-dashes`.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    disable_strategy: Vec<String>,
+    /// Match the remaining debian inputs against all of nixpkgs,
+    /// instead of only the inputs of the nix derivation, with a lower confidence.
+    #[clap(long, value_parser)]
+    global: bool,
+    /// Additional debian infrastructure patterns (`dh-*`), that are not applicable to nix.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    ignore: Vec<String>,
+    /// Patterns, that are removed from the curated debian infrastructure.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    unignore: Vec<String>,
+    /// The amount of ranked candidates, that are kept for unmatched debian inputs.
+    #[clap(long, value_parser)]
+    fuzzy_candidates: Option<usize>,
 }
 
 impl CliArgs {
@@ -31,6 +136,10 @@ impl CliArgs {
     pub(crate) fn discover(&self) -> Option<usize> {
         self.discover
     }
+    pub(crate) fn discover_from(&self) -> Option<&DiscoverInput> {
+        self.discover_from.as_ref()
+    }
+
     pub(crate) fn discover_start(&self) -> Option<usize> {
         self.discover_start
     }
@@ -42,6 +151,34 @@ impl CliArgs {
         self.generate_map.as_ref()
     }
 
+    pub(crate) fn error_summary(&self) -> Option<&String> {
+        self.error_summary.as_ref()
+    }
+
+    pub(crate) fn report(&self) -> Option<&String> {
+        self.report.as_ref()
+    }
+
+    pub(crate) fn report_format(&self) -> ReportFormat {
+        self.report_format
+    }
+
+    pub(crate) fn conflict_policy(&self) -> ConflictPolicy {
+        self.conflict_policy
+    }
+
+    pub(crate) fn map_inputs(&self) -> Vec<PathBuf> {
+        if self.map_input.is_empty() {
+            vec![PathBuf::from("./outputs")]
+        } else {
+            self.map_input.iter().map(PathBuf::from).collect()
+        }
+    }
+
+    pub(crate) fn min_confidence(&self) -> Option<f64> {
+        self.min_confidence
+    }
+
     pub(crate) fn map(&self) -> Option<&String> {
         self.map.as_ref()
     }
@@ -53,4 +190,84 @@ impl CliArgs {
     pub(crate) fn output(&self) -> Option<String> {
         self.output.clone()
     }
+
+    pub(crate) fn resume(&self) -> bool {
+        self.resume || self.retry_failed
+    }
+
+    pub(crate) fn retry_failed(&self) -> bool {
+        self.retry_failed
+    }
+
+    pub(crate) fn refresh(&self) -> bool {
+        self.refresh
+    }
+
+    pub(crate) fn recursive(&self) -> bool {
+        self.recursive
+    }
+
+    pub(crate) fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub(crate) fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    pub(crate) fn http_jobs(&self) -> usize {
+        self.http_jobs.unwrap_or(self.jobs)
+    }
+
+    pub(crate) fn nix_jobs(&self) -> usize {
+        self.nix_jobs.unwrap_or(self.jobs)
+    }
+
+    pub(crate) fn nix_attr(&self) -> Option<&String> {
+        self.nix_attr.as_ref()
+    }
+
+    pub(crate) fn contents(&self) -> Option<&String> {
+        self.contents.as_ref()
+    }
+
+    pub(crate) fn nix_files(&self) -> Option<&String> {
+        self.nix_files.as_ref()
+    }
+
+    pub(crate) fn match_config(&self) -> Option<&String> {
+        self.match_config.as_ref()
+    }
+
+    pub(crate) fn disable_strategy(&self) -> &[String] {
+        self.disable_strategy.as_ref()
+    }
+
+    pub(crate) fn ignore(&self) -> &[String] {
+        self.ignore.as_ref()
+    }
+
+    pub(crate) fn unignore(&self) -> &[String] {
+        self.unignore.as_ref()
+    }
+
+    pub(crate) fn global(&self) -> bool {
+        self.global
+    }
+
+    pub(crate) fn fuzzy_candidates(&self) -> Option<usize> {
+        self.fuzzy_candidates
+    }
+
+    pub(crate) fn elf(&self) -> Option<&String> {
+        self.elf.as_ref()
+    }
+
+    pub(crate) fn shlibs(&self) -> Option<&String> {
+        self.shlibs.as_ref()
+    }
+
+    pub(crate) fn packages(&self) -> Option<&String> {
+        self.packages.as_ref()
+    }
 }
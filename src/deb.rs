@@ -1,9 +1,13 @@
-use std::fs;
+/// Resolving virtual packages through a local `Packages` index.
+pub mod packages;
+
+use std::{thread, time::Duration};
 
 use control_file::ControlFile;
 use serde::{Deserialize, Serialize};
 
 use crate::error::DebNixError;
+use crate::limits::HTTP;
 
 /// Uses the redirect functionality of `tracker.debian.org` in order to find out
 /// the build package that a package output, or a package output + version that
@@ -12,17 +16,62 @@ pub(crate) fn debian_redirect(pkgs: &str) -> Result<String, DebNixError> {
     let tracker_site = "https://tracker.debian.org/pkg/";
     let mut tracker_site = String::from(tracker_site);
     tracker_site.push_str(pkgs.trim());
-    let resp = reqwest::blocking::get(tracker_site)?;
+    let resp = {
+        let _permit = HTTP.acquire();
+        reqwest::blocking::get(tracker_site)?.error_for_status()?
+    };
     let pkgs = resp.url().path();
-    let pkg = pkgs.rsplit_once("/pkg/").unwrap().1;
+    let pkg = pkgs
+        .rsplit_once("/pkg/")
+        .ok_or_else(|| DebNixError::Redirect(format!("Unexpected redirect to {pkgs}")))?
+        .1;
     Ok(String::from(pkg))
 }
 
+/// How often a redirect is attempted, before it is given up on.
+const REDIRECT_ATTEMPTS: u32 = 3;
+/// The delay before the first retry, it doubles with every further retry.
+const REDIRECT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Retries a redirect with an exponential backoff, on errors that are likely transient:
+/// timeouts, connection failures and server errors.
+/// Unknown packages are not retried.
+pub(crate) fn debian_redirect_with_retry(pkg: &str) -> Result<String, DebNixError> {
+    let mut backoff = REDIRECT_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match debian_redirect(pkg) {
+            Ok(redirect) => return Ok(redirect),
+            Err(DebNixError::Reqwest(e)) => {
+                if !is_transient(&e) || attempt >= REDIRECT_ATTEMPTS {
+                    return Err(DebNixError::Redirect(format!(
+                        "{pkg} after {attempt} attempt(s): {e}"
+                    )));
+                }
+                debug!("Retrying redirect of {} in {:?}: {}", pkg, backoff, e);
+            }
+            Err(e) => return Err(e),
+        }
+        thread::sleep(backoff);
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Errors, that are likely to go away on their own:
+/// timeouts, connection failures and server errors.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_timeout() || e.is_connect() || e.status().is_some_and(|status| status.is_server_error())
+}
+
 /// Get's the location of a packages latest version of debians api
 /// relies on a redirect from `sources.debian`.
 pub(crate) fn get_latest_version_api(pkg: &str) -> Result<String, DebNixError> {
     let debian_sources = format!("https://sources.debian.org/api/src/{}/latest/", pkg);
-    let resp = reqwest::blocking::get(debian_sources)?;
+    let resp = {
+        let _permit = HTTP.acquire();
+        reqwest::blocking::get(debian_sources)?
+    };
     let version_path = resp.url().path();
     Ok(version_path.to_string())
 }
@@ -44,11 +93,12 @@ pub(crate) struct ControlFileApi {
 }
 
 impl ControlFileApi {
-    fn new(pkg: &str) -> Result<Self, DebNixError> {
-        let version = get_latest_version_api(pkg).unwrap();
+    pub(crate) fn new(pkg: &str) -> Result<Self, DebNixError> {
+        let version = get_latest_version_api(pkg)?;
         let control_file_api_location =
             format!("https://sources.debian.org{}debian/control", &version);
 
+        let _permit = HTTP.acquire();
         match reqwest::blocking::get(control_file_api_location) {
             Ok(resp) => Ok(serde_json::from_str::<ControlFileApi>(&resp.text()?)?),
             Err(e) => {
@@ -72,6 +122,7 @@ impl ControlFileApi {
     /// Downloads the control file directly from `sources.debian`.
     pub(crate) fn download_control_file(&self) -> Result<String, DebNixError> {
         if let Some(control_file_url) = self.url() {
+            let _permit = HTTP.acquire();
             match reqwest::blocking::get(control_file_url) {
                 Ok(resp) => {
                     return Ok(resp.text()?);
@@ -102,14 +153,6 @@ impl ControlFileApi {
         let pkgs = debian_redirect(pkgs)?;
         ControlFileApi::new(&pkgs)
     }
-
-    pub(crate) fn get_debian_deps(&self) -> Result<Vec<String>, DebNixError> {
-        let download_control_file = &self.download_control_file()?;
-        let parsed_control_file =
-            ControlFile::from_str(download_control_file)?.get_dependencies()?;
-        debug!("Parsed Control File: {:?}", &parsed_control_file);
-        Ok(parsed_control_file)
-    }
 }
 
 pub(crate) fn get_debian_pkg_outputs(pkgs: &str) -> Result<Vec<String>, DebNixError> {
@@ -120,28 +163,205 @@ pub(crate) fn get_debian_pkg_outputs(pkgs: &str) -> Result<Vec<String>, DebNixEr
     Ok(parsed_control_file)
 }
 
-/// Reads the packages from a popcon (popularity contest) file
-/// and then collects them inside of a Vec.
-pub(crate) fn read_popcon(location: &str) -> Result<Vec<String>, DebNixError> {
+/// The role of a debian binary package, debian splits a single source into
+/// multiple binary packages, where nixpkgs uses multiple outputs of a single
+/// derivation instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebRole {
+    /// Headers, pkg-config files and static libraries: `libfoo-dev`
+    Dev,
+    /// Shared libraries: `libfoo1`
+    Lib,
+    /// Executables: `libfoo-bin`, `foo-utils`, `foo-tools`
+    Bin,
+    /// Documentation: `libfoo-doc`
+    Doc,
+    /// Debug symbols: `libfoo1-dbg`
+    Dbg,
+    /// Architecture independent files: `foo-common`, `foo-data`
+    Data,
+    /// Everything else.
+    Out,
+}
+
+impl DebRole {
+    /// Classifies a debian binary package by its naming convention.
+    pub(crate) fn from_name(pkg: &str) -> Self {
+        lazy_static::lazy_static! {
+            // `libfoo1`, `libssl3`, `zlib1g`, `libgtk-3-0`
+            static ref SONAME: regex::Regex = regex::Regex::new(r"^(lib.*|.*lib)[\d.-]+[a-z]?$").unwrap();
+        }
+        match pkg.rsplit_once('-').map(|(_, suffix)| suffix) {
+            Some("dev") => DebRole::Dev,
+            Some("doc") => DebRole::Doc,
+            Some("dbg" | "dbgsym") => DebRole::Dbg,
+            Some("bin" | "utils" | "tools") => DebRole::Bin,
+            Some("common" | "data") => DebRole::Data,
+            _ if SONAME.is_match(pkg) => DebRole::Lib,
+            _ => DebRole::Out,
+        }
+    }
+
+    /// The nixpkgs outputs that correspond to the role, in order of preference.
+    pub(crate) fn nix_outputs(&self) -> &'static [&'static str] {
+        match self {
+            DebRole::Dev => &["dev"],
+            DebRole::Lib => &["lib", "out"],
+            DebRole::Bin => &["bin", "out"],
+            DebRole::Doc => &["doc", "devdoc", "man"],
+            DebRole::Dbg => &["debug"],
+            DebRole::Data | DebRole::Out => &["out"],
+        }
+    }
+
+    /// The debian suffix that marks the role, if there is one.
+    pub(crate) fn strip_suffix(pkg: &str) -> Option<&str> {
+        [
+            "-dev", "-doc", "-dbgsym", "-dbg", "-bin", "-utils", "-tools", "-common", "-data",
+        ]
+        .iter()
+        .find_map(|suffix| pkg.strip_suffix(suffix))
+    }
+}
+
+/// Reads the pkg names of a popularity contest ranking, in the order of their rank:
+/// `1     dpkg                           208093 199253  2137  6629    74 (Dpkg Developers)`
+pub(crate) fn read_popcon_str(contents: &str) -> Vec<String> {
     let mut popcon = vec![];
-    let contents = fs::read_to_string(location)?;
-    let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+    // the ranking has no header, and the maintainers can contain commas
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
     for result in rdr.records().flatten() {
         // TODO: convert this into some error
         if let Some(untrimmed_record) = result.get(0) {
             let record = untrimmed_record.trim_end();
             if !record.starts_with('#') && !record.ends_with("(Not in sid)") {
-                let name = record
-                    .split(' ')
-                    .skip(1)
-                    .take(2)
-                    .collect::<String>();
-                let name = name.trim();
-                if !name.is_empty() {
+                let mut fields = record.split_whitespace();
+                // skips the separator and the totals at the end of the ranking
+                if !fields
+                    .next()
+                    .is_some_and(|rank| rank.parse::<usize>().is_ok())
+                {
+                    continue;
+                }
+                if let Some(name) = fields.next() {
                     popcon.push(name.into());
                 }
             }
         }
     }
-    Ok(popcon)
+    popcon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers a single request on a local port with the response,
+    /// or keeps the connection open without answering.
+    fn serve(response: Option<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/pkg/foo", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            match response {
+                Some(response) => {
+                    let _ = stream.write_all(response.as_bytes());
+                }
+                None => thread::sleep(Duration::from_secs(2)),
+            }
+        });
+        url
+    }
+
+    fn error_of(response: Option<&'static str>) -> reqwest::Error {
+        reqwest::blocking::Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap()
+            .get(serve(response))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .unwrap_err()
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(is_transient(&error_of(Some(
+            "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n"
+        ))));
+        assert!(is_transient(&error_of(None)));
+        // nothing listens on the port anymore
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let error = reqwest::blocking::get(format!("http://{closed}")).unwrap_err();
+        assert!(is_transient(&error));
+    }
+
+    #[test]
+    fn unknown_packages_are_permanent() {
+        assert!(!is_transient(&error_of(Some(
+            "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n"
+        ))));
+    }
+
+    #[test]
+    fn role_from_name() {
+        let cases = [
+            ("libxcb1-dev", DebRole::Dev),
+            ("libxcb-doc", DebRole::Doc),
+            ("libc6-dbg", DebRole::Dbg),
+            ("libxcb1-dbgsym", DebRole::Dbg),
+            ("libglib2.0-bin", DebRole::Bin),
+            ("x11-utils", DebRole::Bin),
+            ("libgtk-3-common", DebRole::Data),
+            ("libxcb1", DebRole::Lib),
+            ("libssl3", DebRole::Lib),
+            ("zlib1g", DebRole::Lib),
+            ("libgtk-3-0", DebRole::Lib),
+            ("i3", DebRole::Out),
+            ("cmake", DebRole::Out),
+        ];
+        for (pkg, role) in cases {
+            assert_eq!(DebRole::from_name(pkg), role, "{pkg}");
+        }
+    }
+
+    #[test]
+    fn strip_role_suffix() {
+        assert_eq!(DebRole::strip_suffix("libxcb1-dev"), Some("libxcb1"));
+        assert_eq!(DebRole::strip_suffix("libc6-dbgsym"), Some("libc6"));
+        assert_eq!(DebRole::strip_suffix("libc6-dbg"), Some("libc6"));
+        assert_eq!(DebRole::strip_suffix("x11-utils"), Some("x11"));
+        assert_eq!(DebRole::strip_suffix("libxcb1"), None);
+    }
+
+    #[test]
+    fn popcon_by_inst() {
+        let contents = "\
+#Format
+#
+#<name> is the package name;
+#rank name                            inst  vote   old recent no-files (maintainer)
+1     dpkg                           208093 199253  2137  6629    74 (Dpkg Developers)
+2     libc6                          208011 193410  4212 10301    88 (GNU Libc Maintainers)
+9999  libxcb-xinerama0               11406   1321  9701   383     1 (Debian X Strike Force)
+10000 python3-pyqt5.qtsvg            11402   1650  9459   293     0 (Debian Python Team, Someone)
+10001 libfoo1                          100      0     0     0   100 (Not in sid)
+-------------------------------------------------------------------------------------
+Total   209152 packages
+";
+        assert_eq!(
+            read_popcon_str(contents),
+            ["dpkg", "libc6", "libxcb-xinerama0", "python3-pyqt5.qtsvg"]
+        );
+    }
 }
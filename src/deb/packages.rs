@@ -0,0 +1,239 @@
+//! Resolution of virtual debian packages through the `Provides` fields
+//! of a local `Packages` index.
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    path::Path,
+};
+
+use crate::error::DebNixError;
+use crate::files::open_index;
+
+/// A concrete package, that provides a virtual one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Provider {
+    name: String,
+    priority: Priority,
+}
+
+/// The `Priority` field of a package, debian installs the
+/// packages with the highest priority by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    Required,
+    Important,
+    Standard,
+    Optional,
+    Extra,
+}
+
+impl Priority {
+    fn from_field(field: &str) -> Self {
+        match field {
+            "required" => Priority::Required,
+            "important" => Priority::Important,
+            "standard" => Priority::Standard,
+            "extra" => Priority::Extra,
+            _ => Priority::Optional,
+        }
+    }
+}
+
+/// The real packages and the providers of the virtual packages,
+/// read from a debian `Packages` index:
+/// `Package: mawk`, `Priority: required`, `Provides: awk`
+#[derive(Debug, Default)]
+pub struct PackagesIndex {
+    real: HashSet<String>,
+    providers: HashMap<String, Vec<Provider>>,
+}
+
+impl PackagesIndex {
+    /// Reads a local, possibly gzip compressed, `Packages` file.
+    pub fn open(location: &Path) -> Result<Self, DebNixError> {
+        Self::parse(open_index(location)?)
+    }
+
+    fn parse(contents: impl BufRead) -> Result<Self, DebNixError> {
+        let mut index = Self::default();
+        let mut package: Option<String> = None;
+        let mut priority = Priority::Optional;
+        let mut provides: Vec<String> = vec![];
+        for line in contents.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                index.insert(package.take(), priority, &mut provides);
+                priority = Priority::Optional;
+                continue;
+            }
+            // Continuation lines belong to multiline fields, like the description.
+            if line.starts_with([' ', '\t']) {
+                continue;
+            }
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match field {
+                "Package" => package = Some(value.into()),
+                "Priority" => priority = Priority::from_field(value),
+                // `Provides: awk, mail-transport-agent (= 4.96)`
+                "Provides" => provides.extend(
+                    value
+                        .split(',')
+                        .filter_map(|provide| provide.split_whitespace().next().map(String::from)),
+                ),
+                _ => {}
+            }
+        }
+        index.insert(package, priority, &mut provides);
+        Ok(index)
+    }
+
+    fn insert(&mut self, package: Option<String>, priority: Priority, provides: &mut Vec<String>) {
+        let Some(name) = package else {
+            provides.clear();
+            return;
+        };
+        for virtual_pkg in provides.drain(..) {
+            let providers = self.providers.entry(virtual_pkg).or_default();
+            if !providers.iter().any(|provider| provider.name == name) {
+                providers.push(Provider {
+                    name: name.clone(),
+                    priority,
+                });
+            }
+        }
+        self.real.insert(name);
+    }
+
+    /// Checks if a package is purely virtual, only provided by other packages.
+    pub fn is_virtual(&self, pkg: &str) -> bool {
+        !self.real.contains(pkg) && self.providers.contains_key(pkg)
+    }
+
+    /// Resolves a virtual package to the concrete package,
+    /// that debian would choose by default.
+    /// Providers are preferred by their priority, then by debian's `default-*` packages,
+    /// ties are broken by the shortest name, in order to stay deterministic.
+    pub fn resolve(&self, pkg: &str) -> Option<&str> {
+        if !self.is_virtual(pkg) {
+            return None;
+        }
+        self.providers
+            .get(pkg)?
+            .iter()
+            .min_by(|a, b| {
+                a.priority
+                    .cmp(&b.priority)
+                    .then_with(|| {
+                        b.name
+                            .starts_with("default-")
+                            .cmp(&a.name.starts_with("default-"))
+                    })
+                    .then_with(|| a.name.len().cmp(&b.name.len()))
+                    .then_with(|| a.name.cmp(&b.name))
+            })
+            .map(|provider| provider.name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const PACKAGES: &str = "\
+Package: mawk
+Priority: required
+Provides: awk
+Description: Pattern scanning and text processing language
+ Mawk is an interpreter for the AWK Programming Language.
+
+Package: gawk
+Priority: optional
+Provides: awk
+
+Package: exim4-daemon-light
+Priority: optional
+Provides: mail-transport-agent (= 4.96), mail-transport-agent
+
+Package: postfix
+Priority: optional
+Provides: mail-transport-agent, default-mta
+
+Package: default-mta
+Priority: optional
+Provides: mail-transport-agent
+
+Package: c-compiler-gcc
+Priority: optional
+Provides: c-compiler
+
+Package: tcc
+Priority: optional
+Provides: c-compiler
+
+Package: emacs
+Priority: optional
+
+Package: emacs-gtk
+Priority: optional
+Provides: emacs
+";
+
+    fn parse(contents: &str) -> PackagesIndex {
+        PackagesIndex::parse(contents.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn providers_are_parsed() {
+        let index = parse(PACKAGES);
+        assert!(index.is_virtual("awk"));
+        assert!(index.is_virtual("mail-transport-agent"));
+        assert!(!index.is_virtual("mawk"));
+        assert!(!index.is_virtual("libfoo"));
+        // versioned and repeated provides are only recorded once
+        let mta = &index.providers["mail-transport-agent"];
+        assert_eq!(
+            mta.iter()
+                .filter(|provider| provider.name == "exim4-daemon-light")
+                .count(),
+            1
+        );
+        // the continuation line of the description is not a field
+        assert_eq!(index.providers.len(), 5);
+    }
+
+    #[test]
+    fn resolve_by_priority_and_default() {
+        let index = parse(PACKAGES);
+        assert_eq!(index.resolve("awk"), Some("mawk"));
+        assert_eq!(index.resolve("mail-transport-agent"), Some("default-mta"));
+    }
+
+    #[test]
+    fn real_packages_are_not_resolved() {
+        let index = parse(PACKAGES);
+        // `default-mta` is provided by postfix, but is a real package as well
+        assert_eq!(index.resolve("default-mta"), None);
+        assert_eq!(index.resolve("emacs"), None);
+        assert_eq!(index.resolve("mawk"), None);
+        assert_eq!(index.resolve("libfoo"), None);
+    }
+
+    #[test]
+    fn ties_are_broken_by_the_shortest_name() {
+        let index = parse(PACKAGES);
+        assert_eq!(index.resolve("c-compiler"), Some("tcc"));
+        let index = parse("Package: bbb\nProvides: virt\n\nPackage: aaa\nProvides: virt\n");
+        assert_eq!(index.resolve("virt"), Some("aaa"));
+    }
+
+    #[test]
+    fn open_a_packages_file() {
+        let dir = TempDir::new("packages");
+        let index = PackagesIndex::open(&dir.write("Packages", PACKAGES)).unwrap();
+        assert_eq!(index.resolve("awk"), Some("mawk"));
+    }
+}
@@ -0,0 +1,399 @@
+//! Concurrent discovery of many packages, through a bounded pool of workers.
+//! The requests to debian and the `nix` subprocesses are limited separately
+//! (see [`crate::limits`]), since they are what the workers spend most of their time waiting on.
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{create_dir_all, File},
+    io::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::error::DebNixError;
+use crate::failures::ErrorRecord;
+use crate::journal::Journal;
+use crate::state::{read_outputs, to_canonical_json, DebNixOutputs, State};
+
+/// How often the deadline and the signals are checked.
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The amount of SIGINT and SIGTERM signals, that arrived.
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+/// On the first signal the sweep stops gracefully, the pkgs in flight are finished.
+fn install_signal_handler() {
+    if let Err(e) = ctrlc::set_handler(|| {
+        if SIGNALS.fetch_add(1, Ordering::Relaxed) == 0 {
+            eprintln!("Finishing the pkgs in flight, interrupt again to stop immediately.");
+        }
+    }) {
+        warn!("Couldn't install the signal handler: {}", e);
+    }
+}
+
+/// The pkgs, that are waiting for a worker, together with their depth
+/// in the dependency closure of a recursive discovery.
+struct Queue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+struct QueueState {
+    pending: VecDeque<(String, usize)>,
+    /// Pkgs, whose dependencies might still be pushed.
+    in_flight: usize,
+    stopped: bool,
+}
+
+impl Queue {
+    fn new(pending: VecDeque<(String, usize)>) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                pending,
+                in_flight: 0,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Takes the next pkg, waits while pkgs in flight can still push new ones.
+    /// Every pkg, that is taken, is in flight until its [`InFlight`] guard is dropped.
+    fn pop(&self) -> Option<(String, usize)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            if let Some(next) = state.pending.pop_front() {
+                state.in_flight += 1;
+                return Some(next);
+            }
+            if state.in_flight == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn push(&self, pkg: String, depth: usize) {
+        self.state.lock().unwrap().pending.push_back((pkg, depth));
+        self.changed.notify_one();
+    }
+
+    /// No further pkgs are handed out.
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.changed.notify_all();
+    }
+
+    fn is_drained(&self) -> bool {
+        self.state.lock().unwrap().pending.is_empty()
+    }
+}
+
+/// Marks a pkg as done once dropped, even if its worker panicked,
+/// so that the other workers don't wait for its dependencies.
+struct InFlight<'a>(&'a Queue);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.changed.notify_all();
+    }
+}
+
+/// How a discovery run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Finished {
+    Completed,
+    /// The timeout was exceeded, the remaining pkgs were not started.
+    TimedOut,
+    /// A signal arrived, the remaining pkgs were not started.
+    Interrupted,
+}
+
+impl Finished {
+    /// The exit status of the program, that distinguishes the ways a run ended.
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            Finished::Completed => 0,
+            Finished::TimedOut => 3,
+            // like shells report processes, that were terminated by SIGINT
+            Finished::Interrupted => 130,
+        }
+    }
+}
+
+/// Discovers the pkgs with the configured amount of workers,
+/// and writes an output, or an error file for every one of them into the destination.
+/// Pkgs that the journal records as done, or failed are skipped,
+/// when resuming, the pending pkgs of the journal are discovered as well.
+/// A recursive discovery enqueues the dependencies of every discovered pkg,
+/// that are not yet part of the journal, up to the maximum depth.
+pub(crate) fn discover_all(
+    state: &State,
+    pkgs: &[String],
+    destination: &str,
+) -> Result<Finished, DebNixError> {
+    let error_destination = format!("{destination}/error");
+    create_dir_all(&error_destination)
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {error_destination}")))?;
+
+    let mut journal = Journal::open(Path::new(destination))?;
+    journal.add(pkgs, Path::new(destination));
+    if state.retry_failed() {
+        journal.retry_failed();
+    }
+    let mut pkgs = pkgs
+        .iter()
+        .filter(|pkg| journal.is_pending(pkg))
+        .cloned()
+        .collect::<Vec<String>>();
+    let mut queued = pkgs.iter().cloned().collect::<HashSet<String>>();
+    if state.resume() {
+        let pending = journal
+            .pending()
+            .filter(|pkg| !queued.contains(*pkg))
+            .cloned()
+            .collect::<Vec<String>>();
+        queued.extend(pending.iter().cloned());
+        pkgs.extend(pending);
+    }
+    // the discovered pkgs are checked for changes, before they are discovered again
+    let mut refresh = HashMap::new();
+    if state.refresh() {
+        for (_, outputs) in read_outputs(Path::new(destination))? {
+            let Some(pkg) = outputs.pkgs_name().map(String::from) else {
+                continue;
+            };
+            if queued.insert(pkg.clone()) {
+                pkgs.push(pkg.clone());
+            }
+            refresh.insert(pkg, outputs);
+        }
+    }
+    journal.save()?;
+
+    let queue = Queue::new(
+        pkgs.iter()
+            .map(|pkg| (pkg.clone(), journal.depth(pkg)))
+            .collect(),
+    );
+    let journal = Mutex::new(journal);
+
+    install_signal_handler();
+    let deadline = state
+        .timeout()
+        .map(|minutes| Instant::now() + Duration::from_secs(minutes as u64 * 60));
+
+    let done = AtomicBool::new(false);
+    let mut finished = Finished::Completed;
+    // the queue of a recursive discovery grows with the dependencies
+    let workers = if state.recursive() {
+        state.jobs().max(1)
+    } else {
+        state.jobs().clamp(1, pkgs.len().max(1))
+    };
+    info!("Discovering {} pkgs with {} workers", pkgs.len(), workers);
+    let discover = |pkg: &str| -> Result<Option<DebNixOutputs>, DebNixError> {
+        if let Some(outputs) = refresh.get(pkg) {
+            match state.changed_since(outputs) {
+                Ok(Some(reason)) => info!("Refreshing {}: {}", pkg, reason),
+                Ok(None) => return Ok(None),
+                // the pkg is discovered again, which records the error
+                Err(e) => info!("Refreshing {}: {}", pkg, e),
+            }
+        }
+        journal.lock().unwrap().start(pkg)?;
+        let result = discover_into(state, pkg, destination)?;
+        let reason = result.as_ref().map(|_| ()).map_err(String::clone);
+        journal.lock().unwrap().finish(pkg, reason)?;
+        Ok(result.ok())
+    };
+    thread::scope(|scope| {
+        // Stops the workers from taking new pkgs, once the deadline passed, or a signal
+        // arrived. A second signal records the pkgs in flight as interrupted and exits.
+        let watcher = scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                if SIGNALS.load(Ordering::Relaxed) > 1 {
+                    let mut journal = journal.lock().unwrap();
+                    journal.interrupt_running();
+                    if let Err(e) = journal.save() {
+                        error!("Flushing the journal: {}", e);
+                    }
+                    eprintln!("Interrupted, the pkgs in flight are recorded as interrupted.");
+                    std::process::exit(Finished::Interrupted.exit_code());
+                }
+                if SIGNALS.load(Ordering::Relaxed) > 0 {
+                    queue.stop();
+                    return Finished::Interrupted;
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    queue.stop();
+                    return Finished::TimedOut;
+                }
+                thread::sleep(WATCH_INTERVAL);
+            }
+            Finished::Completed
+        });
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| -> Result<(), DebNixError> {
+                    while let Some((pkg, depth)) = queue.pop() {
+                        let _in_flight = InFlight(&queue);
+                        let result = discover(&pkg).and_then(|outputs| {
+                            let Some(outputs) = outputs else {
+                                return Ok(());
+                            };
+                            if state.recursive() && depth < state.max_depth() {
+                                let mut journal = journal.lock().unwrap();
+                                for dep in outputs.applicable_deps() {
+                                    if journal.enqueue(dep, depth + 1)? {
+                                        queue.push(dep.clone(), depth + 1);
+                                    }
+                                }
+                            }
+                            Ok(())
+                        });
+                        if let Err(e) = result {
+                            queue.stop();
+                            return Err(e);
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join())
+            .collect::<Vec<_>>();
+        // the watcher has to stop, before a panic of a worker is propagated
+        done.store(true, Ordering::Relaxed);
+        finished = watcher.join().expect("The discovery watcher panicked.");
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .fold(Ok(()), Result::and)
+    })?;
+    // a signal, or the deadline during the last pkgs still leaves the sweep complete
+    if queue.is_drained() {
+        finished = Finished::Completed;
+    }
+    journal.into_inner().unwrap().save()?;
+    Ok(finished)
+}
+
+/// Discovers a single pkg, and writes its output or error file.
+/// The error of the discovery is returned as the reason for the journal,
+/// errors while writing the files abort the sweep.
+fn discover_into(
+    state: &State,
+    pkg: &str,
+    destination: &str,
+) -> Result<Result<DebNixOutputs, String>, DebNixError> {
+    let error_dir = Path::new(destination).join("error");
+    let destination = format!("{}/{}-debnix.json", destination, pkg);
+    match state.discover_pkg(pkg.into()) {
+        Ok(outputs) => {
+            let serialized = to_canonical_json(&outputs)?;
+            let mut file = File::create(&destination)
+                .map_err(|e| DebNixError::IoPath(format!("{e}: {destination}")))?;
+            file.write_all(serialized.as_bytes())?;
+            error!("Written to location: {}", &destination);
+            // the error of a previous attempt is outdated
+            ErrorRecord::remove(&error_dir, pkg)?;
+            Ok(Ok(outputs))
+        }
+        Err(e) => {
+            error!("Discover Error: {}", e);
+            let error_destination = ErrorRecord::location(&error_dir, pkg);
+            match ErrorRecord::new(pkg, &e).write(&error_dir) {
+                Ok(()) => error!("Written to location: {}", error_destination.display()),
+                Err(e) => error!("Could not write the error record: {}", e),
+            }
+            Ok(Err(e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(pkgs: &[&str]) -> Queue {
+        Queue::new(pkgs.iter().map(|pkg| (String::from(*pkg), 0)).collect())
+    }
+
+    #[test]
+    fn drain_waits_for_pushes_of_pkgs_in_flight() {
+        let queue = queue(&["a"]);
+        assert_eq!(queue.pop(), Some(("a".into(), 0)));
+        let in_flight = InFlight(&queue);
+        thread::scope(|scope| {
+            // nothing is pending, but `a` can still push its dependencies
+            let waiting = scope.spawn(|| {
+                let next = queue.pop();
+                let _in_flight = next.as_ref().map(|_| InFlight(&queue));
+                next
+            });
+            thread::sleep(Duration::from_millis(50));
+            assert!(!waiting.is_finished());
+            queue.push("b".into(), 1);
+            assert_eq!(waiting.join().unwrap(), Some(("b".into(), 1)));
+        });
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| queue.pop());
+            drop(in_flight);
+            assert_eq!(waiting.join().unwrap(), None);
+        });
+        assert!(queue.is_drained());
+    }
+
+    #[test]
+    fn stop_leaves_the_pending_pkgs() {
+        let queue = queue(&["a", "b", "c"]);
+        assert_eq!(queue.pop(), Some(("a".into(), 0)));
+        let _in_flight = InFlight(&queue);
+        queue.stop();
+        assert_eq!(queue.pop(), None);
+        assert!(!queue.is_drained());
+    }
+
+    #[test]
+    fn stop_wakes_waiting_workers() {
+        let queue = queue(&["a"]);
+        queue.pop().unwrap();
+        let _in_flight = InFlight(&queue);
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| queue.pop());
+            thread::sleep(Duration::from_millis(50));
+            queue.stop();
+            assert_eq!(waiting.join().unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn panicking_workers_are_not_in_flight() {
+        let queue = queue(&["a", "b"]);
+        thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                let (pkg, _) = queue.pop().unwrap();
+                let _in_flight = InFlight(&queue);
+                panic!("discovering {pkg}");
+            });
+            assert!(worker.join().is_err());
+        });
+        // the other worker neither deadlocks, nor waits for the panicked one
+        assert_eq!(queue.pop(), Some(("b".into(), 0)));
+        drop(InFlight(&queue));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.state.lock().unwrap().in_flight, 0);
+    }
+}
@@ -0,0 +1,312 @@
+//! Resolving the runtime dependencies of prebuilt binaries and debian packages,
+//! from their `DT_NEEDED` sonames over debian `shlibs` to nixpkgs attributes.
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DebNixError;
+use crate::files::{open_index, FileKey, FileMatcher, NixFileProvider};
+
+/// The shared libraries, that a prebuilt binary or debian package needs at runtime,
+/// translated into debian package names and nixpkgs attributes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ElfOutputs {
+    file: String,
+    needed: Vec<SonameMatch>,
+    /// The nix attribute outputs, that provide all the resolved sonames.
+    nix_deps: Vec<String>,
+    /// Sonames, that couldn't be resolved to a nix attribute.
+    unresolved: Vec<String>,
+}
+
+/// A single `DT_NEEDED` entry.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SonameMatch {
+    soname: String,
+    deb_pkgs: Vec<String>,
+    nix: Option<String>,
+}
+
+/// The sonames that debian packages provide, read from `shlibs` files:
+/// `libxcb 1 libxcb1 (>= 1.8)`
+#[derive(Debug, Default)]
+pub struct Shlibs {
+    providers: HashMap<String, Vec<String>>,
+}
+
+impl Shlibs {
+    /// Reads a single `shlibs` file,
+    /// or every `*.shlibs` file inside of a directory (`/var/lib/dpkg/info`).
+    pub fn open(location: &Path) -> Result<Self, DebNixError> {
+        let mut shlibs = Self::default();
+        if location.is_dir() {
+            let mut paths = fs::read_dir(location)?
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "shlibs"))
+                .collect::<Vec<_>>();
+            // providers are listed in the same order, independent of the directory
+            paths.sort();
+            for path in paths {
+                shlibs.read(&path)?;
+            }
+        } else {
+            shlibs.read(location)?;
+        }
+        Ok(shlibs)
+    }
+
+    fn read(&mut self, location: &Path) -> Result<(), DebNixError> {
+        self.parse(open_index(location)?)
+    }
+
+    fn parse(&mut self, contents: impl std::io::BufRead) -> Result<(), DebNixError> {
+        for line in contents.lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(version)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            // Skip comments and entries for specific package types: `udeb: libfoo 1 ...`
+            if name.starts_with('#') || name.ends_with(':') {
+                continue;
+            }
+            let Some(pkg) = fields.next() else {
+                continue;
+            };
+            let pkg = pkg.trim_end_matches(',');
+            // `libfoo.so.1` -> `libfoo 1`, `libfoo-1.2.so` -> `libfoo 1.2`
+            for soname in [
+                format!("{name}.so.{version}"),
+                format!("{name}-{version}.so"),
+            ] {
+                let providers = self.providers.entry(soname).or_default();
+                if !providers.iter().any(|provider| provider == pkg) {
+                    providers.push(pkg.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The debian packages, that provide a soname.
+    pub fn providers(&self, soname: &str) -> &[String] {
+        self.providers.get(soname).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Resolves sonames to debian packages and nixpkgs attributes.
+pub struct SonameResolver<'a> {
+    files: &'a FileMatcher,
+    shlibs: Option<&'a Shlibs>,
+}
+
+impl<'a> SonameResolver<'a> {
+    pub fn new(files: &'a FileMatcher, shlibs: Option<&'a Shlibs>) -> Self {
+        Self { files, shlibs }
+    }
+
+    /// The debian packages that ship the soname, `shlibs` take precedence
+    /// over the `Contents` index, since they are maintained by the library packages themselves.
+    pub fn deb_pkgs(&self, soname: &str) -> Vec<String> {
+        if let Some(providers) = self
+            .shlibs
+            .map(|shlibs| shlibs.providers(soname))
+            .filter(|providers| !providers.is_empty())
+        {
+            return providers.to_vec();
+        }
+        self.files
+            .contents()
+            .owners(&FileKey::SharedLib(soname.into()))
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    /// The nix attribute output, that provides the soname.
+    /// Outputs dedicated to libraries are preferred.
+    pub fn nix_attr(&self, soname: &str) -> Option<NixFileProvider> {
+        let mut providers = self
+            .files
+            .nix_files()
+            .providers(&FileKey::SharedLib(soname.into()));
+        providers.sort_by_key(|provider| {
+            (
+                !matches!(provider.output(), "lib" | "out"),
+                provider.attr().len(),
+                provider.attr().to_owned(),
+            )
+        });
+        providers.into_iter().next()
+    }
+
+    pub fn resolve(&self, file: &Path) -> Result<ElfOutputs, DebNixError> {
+        let mut needed = vec![];
+        let mut nix_deps = vec![];
+        let mut unresolved = vec![];
+        for soname in needed_sonames(file)? {
+            let nix = self.nix_attr(&soname).map(|provider| provider.target());
+            match &nix {
+                Some(target) => nix_deps.push(target.clone()),
+                None => unresolved.push(soname.clone()),
+            }
+            needed.push(SonameMatch {
+                deb_pkgs: self.deb_pkgs(&soname),
+                soname,
+                nix,
+            });
+        }
+        nix_deps.sort();
+        nix_deps.dedup();
+        Ok(ElfOutputs {
+            file: file.display().to_string(),
+            needed,
+            nix_deps,
+            unresolved,
+        })
+    }
+}
+
+/// The first bytes of every ELF file.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Reads the `DT_NEEDED` entries of a binary, or of all binaries inside of a `.deb`.
+pub fn needed_sonames(location: &Path) -> Result<Vec<String>, DebNixError> {
+    if location.extension().is_some_and(|ext| ext == "deb") {
+        return needed_from_deb(location);
+    }
+    let bytes = fs::read(location)
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+    needed_from_elf(&bytes)
+}
+
+fn needed_from_elf(bytes: &[u8]) -> Result<Vec<String>, DebNixError> {
+    let elf = goblin::elf::Elf::parse(bytes).map_err(|e| DebNixError::Elf(e.to_string()))?;
+    Ok(elf.libraries.iter().map(|lib| String::from(*lib)).collect())
+}
+
+/// Collects the `DT_NEEDED` entries of every binary inside of the data archive
+/// of a debian package. Sonames that the package ships itself are left out.
+fn needed_from_deb(location: &Path) -> Result<Vec<String>, DebNixError> {
+    let file = File::open(location)
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+    let mut archive = ar::Archive::new(file);
+    let mut needed = vec![];
+    let mut shipped = vec![];
+    while let Some(entry) = archive.next_entry() {
+        let entry = entry?;
+        let name = String::from_utf8_lossy(entry.header().identifier()).into_owned();
+        let Some(compression) = name.strip_prefix("data.tar") else {
+            continue;
+        };
+        let data: Box<dyn Read> = match compression {
+            "" => Box::new(entry),
+            ".gz" => Box::new(flate2::read::GzDecoder::new(entry)),
+            ".xz" => Box::new(xz2::read::XzDecoder::new(entry)),
+            ".zst" => Box::new(zstd::Decoder::new(entry)?),
+            _ => {
+                return Err(DebNixError::Elf(format!(
+                    "Unsupported data archive compression: {name}"
+                )))
+            }
+        };
+        for file in tar::Archive::new(data).entries()? {
+            let mut file = file?;
+            let path = file.path()?.to_string_lossy().into_owned();
+            if let Some(FileKey::SharedLib(soname)) = FileKey::from_debian_path(&path) {
+                shipped.push(soname);
+            }
+            if !file.header().entry_type().is_file() {
+                continue;
+            }
+            // only binaries are read completely, the rest of the entry is skipped
+            let mut bytes = vec![0; ELF_MAGIC.len()];
+            if file.read_exact(&mut bytes).is_err() || bytes != ELF_MAGIC {
+                continue;
+            }
+            file.read_to_end(&mut bytes)?;
+            match needed_from_elf(&bytes) {
+                Ok(sonames) => needed.extend(sonames),
+                Err(e) => debug!("Skipping {}: {}", path, e),
+            }
+        }
+        break;
+    }
+    needed.sort();
+    needed.dedup();
+    needed.retain(|soname| !shipped.contains(soname));
+    Ok(needed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn parse(contents: &str) -> Shlibs {
+        let mut shlibs = Shlibs::default();
+        shlibs.parse(contents.as_bytes()).unwrap();
+        shlibs
+    }
+
+    #[test]
+    fn shlibs_sonames() {
+        let shlibs = parse(
+            "\
+libxcb 1 libxcb1 (>= 1.8)
+libgtk-3 0 libgtk-3-0 (>= 3.24.0)
+libssl 3 libssl3t64 (>= 3.0.0), libssl3 (>= 3.0.0)
+",
+        );
+        assert_eq!(shlibs.providers("libxcb.so.1"), &["libxcb1"]);
+        assert_eq!(shlibs.providers("libxcb-1.so"), &["libxcb1"]);
+        assert_eq!(shlibs.providers("libgtk-3.so.0"), &["libgtk-3-0"]);
+        // alternatives only name the first provider
+        assert_eq!(shlibs.providers("libssl.so.3"), &["libssl3t64"]);
+        assert!(shlibs.providers("libfoo.so.1").is_empty());
+    }
+
+    #[test]
+    fn shlibs_skip_comments_and_package_types() {
+        let shlibs = parse(
+            "\
+# libxcb 1 libxcb-comment
+udeb: libxcb 1 libxcb1-udeb
+libxcb 1 libxcb1
+
+libincomplete 1
+",
+        );
+        assert_eq!(shlibs.providers("libxcb.so.1"), &["libxcb1"]);
+        assert!(shlibs.providers("libincomplete.so.1").is_empty());
+    }
+
+    #[test]
+    fn shlibs_providers_are_deduplicated() {
+        let shlibs = parse("libxcb 1 libxcb1\nlibxcb 1 libxcb1 (>= 1.8)\n");
+        assert_eq!(shlibs.providers("libxcb.so.1"), &["libxcb1"]);
+    }
+
+    #[test]
+    fn shlibs_directory_is_read_in_order() {
+        let dir = TempDir::new("elf-shlibs");
+        dir.write("libxcb1-b.shlibs", "libxcb 1 libxcb1-b\n");
+        dir.write("libxcb1-a.shlibs", "libxcb 1 libxcb1-a\n");
+        dir.write("libxcb1.list", "libxcb 1 libxcb1-list\n");
+        let shlibs = Shlibs::open(dir.path()).unwrap();
+        assert_eq!(shlibs.providers("libxcb.so.1"), &["libxcb1-a", "libxcb1-b"]);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    fn needed_sonames_of_the_test_binary() {
+        let needed = needed_sonames(&std::env::current_exe().unwrap()).unwrap();
+        assert!(needed.iter().any(|soname| soname.starts_with("libc.so")));
+    }
+}
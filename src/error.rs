@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,17 +13,111 @@ pub enum DebNixError {
     /// Deserialization Error
     #[error("Deserialization Error: {0}")]
     Serde(#[from] serde_json::Error),
-    #[error("Utf8 Conversion Error")]
+    #[error("Utf8 Conversion Error: {0}")]
     Utf8(#[from] std::str::Utf8Error),
     /// Reqwest Error
-    #[error("Reqwest Error")]
+    #[error("Reqwest Error: {0}")]
     Reqwest(#[from] reqwest::Error),
-    #[error("DebControl Error")]
+    #[error("DebControl Error: {0}")]
     DebControl(String),
-    #[error("DebControl Error")]
+    #[error("Control File Error: {0}")]
     ControlFile(#[from] control_file::ControlFileError),
-    #[error("Nix Error")]
+    #[error("Nix Error: {0}")]
     Nix(String),
+    #[error("Nix Index Error: {0}")]
+    NixIndex(String),
+    #[error("Elf Error: {0}")]
+    Elf(String),
+    #[error("Configuration Error: {0}")]
+    Config(String),
+    #[error("Redirect Error: {0}")]
+    Redirect(String),
     #[error("Nothing to Match: {0}")]
     NoMatches(String),
+    /// An error during a specific phase of the discovery of a pkg.
+    #[error("{phase}: {source}")]
+    InPhase {
+        phase: Phase,
+        #[source]
+        source: Box<DebNixError>,
+    },
+}
+
+impl DebNixError {
+    /// The kind of the error, independent of the phase it happened in.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DebNixError::Io(_) => "io",
+            DebNixError::IoPath(_) => "io-path",
+            DebNixError::Serde(_) => "serde",
+            DebNixError::Utf8(_) => "utf8",
+            DebNixError::Reqwest(_) => "reqwest",
+            DebNixError::DebControl(_) => "deb-control",
+            DebNixError::ControlFile(_) => "control-file",
+            DebNixError::Nix(_) => "nix",
+            DebNixError::NixIndex(_) => "nix-index",
+            DebNixError::Elf(_) => "elf",
+            DebNixError::Config(_) => "config",
+            DebNixError::Redirect(_) => "redirect",
+            DebNixError::NoMatches(_) => "no-matches",
+            DebNixError::InPhase { source, .. } => source.kind(),
+        }
+    }
+
+    pub fn phase(&self) -> Option<Phase> {
+        match self {
+            DebNixError::InPhase { phase, .. } => Some(*phase),
+            _ => None,
+        }
+    }
+
+    /// The error without the phase it happened in.
+    pub fn inner(&self) -> &DebNixError {
+        match self {
+            DebNixError::InPhase { source, .. } => source.inner(),
+            e => e,
+        }
+    }
+}
+
+/// The phases of the discovery of a single pkg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Resolving the source pkg of a binary pkg.
+    Redirect,
+    /// Querying and downloading the control file.
+    ControlFetch,
+    /// Parsing the dependencies of the control file.
+    Parse,
+    /// Evaluating the derivations of the nix pkgs.
+    NixEval,
+    Matching,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let phase = match self {
+            Phase::Redirect => "redirect",
+            Phase::ControlFetch => "control-fetch",
+            Phase::Parse => "parse",
+            Phase::NixEval => "nix-eval",
+            Phase::Matching => "matching",
+        };
+        f.write_str(phase)
+    }
+}
+
+/// Records the phase, that an error happened in.
+pub trait InPhase<T> {
+    fn in_phase(self, phase: Phase) -> Result<T, DebNixError>;
+}
+
+impl<T, E: Into<DebNixError>> InPhase<T> for Result<T, E> {
+    fn in_phase(self, phase: Phase) -> Result<T, DebNixError> {
+        self.map_err(|e| DebNixError::InPhase {
+            phase,
+            source: Box::new(e.into()),
+        })
+    }
 }
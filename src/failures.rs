@@ -0,0 +1,311 @@
+//! The error records of pkgs, whose discovery failed.
+//! They are stored as `error/<pkg>.json` in the output directory.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DebNixError, Phase};
+use crate::state::to_canonical_json;
+
+/// The kind of the plain text error files, that were written before the records.
+const UNKNOWN_KIND: &str = "unknown";
+
+/// A single failed discovery.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pkg: String,
+    kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    phase: Option<Phase>,
+    message: String,
+    /// The underlying errors, the outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    causes: Vec<String>,
+    timestamp: DateTime<Utc>,
+}
+
+impl ErrorRecord {
+    pub fn new(pkg: &str, error: &DebNixError) -> Self {
+        let inner = error.inner();
+        let message = inner.to_string();
+        let mut causes: Vec<String> = vec![];
+        let mut source = std::error::Error::source(inner);
+        while let Some(cause) = source {
+            let cause_message = cause.to_string();
+            // most variants already include their source in the message
+            if !message.contains(&cause_message)
+                && !causes.iter().any(|known| known.contains(&cause_message))
+            {
+                causes.push(cause_message);
+            }
+            source = cause.source();
+        }
+        Self {
+            pkg: pkg.into(),
+            kind: error.kind().into(),
+            phase: error.phase(),
+            message,
+            causes,
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// The location of the record of a pkg inside of the error directory.
+    pub fn location(error_dir: &Path, pkg: &str) -> PathBuf {
+        error_dir.join(format!("{pkg}.json"))
+    }
+
+    /// Reads a record, plain text error files are read as errors of an unknown kind.
+    pub fn open(location: &Path) -> Result<Self, DebNixError> {
+        let contents = fs::read_to_string(location)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+        if location
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            if let Ok(record) = serde_json::from_str(&contents) {
+                return Ok(record);
+            }
+        }
+        let timestamp = fs::metadata(location)
+            .and_then(|metadata| metadata.modified())
+            .map_or_else(|_| Utc::now(), DateTime::<Utc>::from);
+        // a broken record is named like a record, pkg names can contain dots
+        let pkg = location
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .map(|name| name.strip_suffix(".json").unwrap_or(&name).to_owned())
+            .unwrap_or_default();
+        Ok(Self {
+            pkg,
+            kind: UNKNOWN_KIND.into(),
+            phase: None,
+            message: contents.trim().into(),
+            causes: vec![],
+            timestamp,
+        })
+    }
+
+    pub fn write(&self, error_dir: &Path) -> Result<(), DebNixError> {
+        let location = Self::location(error_dir, &self.pkg);
+        fs::write(&location, to_canonical_json(self)?)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))
+    }
+
+    /// Removes the record of a pkg, as well as a plain text error file.
+    pub fn remove(error_dir: &Path, pkg: &str) -> Result<(), DebNixError> {
+        for location in [Self::location(error_dir, pkg), error_dir.join(pkg)] {
+            if location.exists() {
+                fs::remove_file(&location)
+                    .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn message(&self) -> &str {
+        self.message.as_ref()
+    }
+}
+
+/// The failures of a run, aggregated by their kind.
+#[derive(Debug, Default, Serialize)]
+pub struct ErrorSummary {
+    total: usize,
+    kinds: BTreeMap<String, KindSummary>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct KindSummary {
+    count: usize,
+    /// `{phase: count}`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    phases: BTreeMap<Phase, usize>,
+    pkgs: Vec<String>,
+}
+
+impl KindSummary {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn phases(&self) -> &BTreeMap<Phase, usize> {
+        &self.phases
+    }
+}
+
+impl ErrorSummary {
+    /// Summarizes the error records of an output directory.
+    pub fn from_output(output: &Path) -> Result<Self, DebNixError> {
+        let error_dir = output.join("error");
+        let mut summary = Self::default();
+        if !error_dir.exists() {
+            return Ok(summary);
+        }
+        let mut locations = fs::read_dir(&error_dir)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {}", error_dir.display())))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|location| location.is_file())
+            .collect::<Vec<_>>();
+        // a plain text error file is outdated by the record of the same pkg
+        let records = locations.clone();
+        locations.retain(|location| {
+            location
+                .extension()
+                .is_some_and(|extension| extension == "json")
+                || !records.contains(&location.with_file_name(format!(
+                    "{}.json",
+                    location.file_name().unwrap_or_default().to_string_lossy()
+                )))
+        });
+        locations.sort();
+        for location in locations {
+            summary.add(ErrorRecord::open(&location)?);
+        }
+        Ok(summary)
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn kinds(&self) -> &BTreeMap<String, KindSummary> {
+        &self.kinds
+    }
+
+    fn add(&mut self, record: ErrorRecord) {
+        self.total += 1;
+        let kind = self.kinds.entry(record.kind).or_default();
+        kind.count += 1;
+        if let Some(phase) = record.phase {
+            *kind.phases.entry(phase).or_default() += 1;
+        }
+        kind.pkgs.push(record.pkg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InPhase;
+    use crate::testing::TempDir;
+
+    /// An error, whose message doesn't include its source.
+    #[derive(Debug, thiserror::Error)]
+    #[error("outer")]
+    struct Outer(#[source] Inner);
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("inner cause")]
+    struct Inner;
+
+    fn in_phase(error: DebNixError, phase: Phase) -> DebNixError {
+        Err::<(), _>(error).in_phase(phase).unwrap_err()
+    }
+
+    #[test]
+    fn records_of_errors_in_a_phase() {
+        let error = in_phase(DebNixError::Redirect("gone".into()), Phase::Redirect);
+        let record = ErrorRecord::new("i3", &error);
+        assert_eq!(record.pkg, "i3");
+        assert_eq!(record.kind, "redirect");
+        assert_eq!(record.phase, Some(Phase::Redirect));
+        assert_eq!(record.message(), "Redirect Error: gone");
+        assert!(record.causes.is_empty());
+    }
+
+    #[test]
+    fn records_list_the_causes_missing_from_the_message() {
+        let error = DebNixError::Io(std::io::Error::other(Outer(Inner)));
+        let record = ErrorRecord::new("i3", &error);
+        assert_eq!(record.kind, "io");
+        assert_eq!(record.phase, None);
+        assert_eq!(record.message(), "IoError: outer");
+        assert_eq!(record.causes, ["inner cause"]);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let dir = TempDir::new("failures-round-trip");
+        let error = in_phase(DebNixError::Nix("no attribute".into()), Phase::NixEval);
+        let record = ErrorRecord::new("libglib2.0-bin", &error);
+        record.write(dir.path()).unwrap();
+        let location = ErrorRecord::location(dir.path(), "libglib2.0-bin");
+        assert!(location.ends_with("libglib2.0-bin.json"));
+        let opened = ErrorRecord::open(&location).unwrap();
+        assert_eq!(opened.pkg, record.pkg);
+        assert_eq!(opened.kind, record.kind);
+        assert_eq!(opened.phase, record.phase);
+        assert_eq!(opened.message, record.message);
+        assert_eq!(opened.timestamp, record.timestamp);
+    }
+
+    #[test]
+    fn legacy_plain_text_files() {
+        let dir = TempDir::new("failures-legacy");
+        let legacy = dir.write("i3", "Control file not found\n");
+        let record = ErrorRecord::open(&legacy).unwrap();
+        assert_eq!(record.pkg, "i3");
+        assert_eq!(record.kind, UNKNOWN_KIND);
+        assert_eq!(record.phase, None);
+        assert_eq!(record.message(), "Control file not found");
+        // a broken record is named after the pkg
+        let broken = dir.write("libglib2.0-bin.json", "Control file not found");
+        let record = ErrorRecord::open(&broken).unwrap();
+        assert_eq!(record.pkg, "libglib2.0-bin");
+        assert_eq!(record.kind, UNKNOWN_KIND);
+        assert!(ErrorRecord::open(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn remove_records_and_plain_text_files() {
+        let dir = TempDir::new("failures-remove");
+        ErrorRecord::new("i3", &DebNixError::Config("broken".into()))
+            .write(dir.path())
+            .unwrap();
+        dir.write("i3", "Control file not found");
+        dir.write("i3-wm", "Control file not found");
+        ErrorRecord::remove(dir.path(), "i3").unwrap();
+        assert!(!ErrorRecord::location(dir.path(), "i3").exists());
+        assert!(!dir.path().join("i3").exists());
+        assert!(dir.path().join("i3-wm").exists());
+        // nothing to remove
+        ErrorRecord::remove(dir.path(), "i3").unwrap();
+    }
+
+    #[test]
+    fn summary_of_an_output_directory() {
+        let output = TempDir::new("failures-summary");
+        assert_eq!(ErrorSummary::from_output(output.path()).unwrap().total(), 0);
+        let error_dir = output.path().join("error");
+        fs::create_dir_all(&error_dir).unwrap();
+        let redirect = in_phase(DebNixError::Redirect("gone".into()), Phase::Redirect);
+        ErrorRecord::new("a", &redirect).write(&error_dir).unwrap();
+        ErrorRecord::new("b", &redirect).write(&error_dir).unwrap();
+        ErrorRecord::new("c", &DebNixError::Config("broken".into()))
+            .write(&error_dir)
+            .unwrap();
+        // outdated by the record of `c`
+        output.write("error/c", "Control file not found");
+        output.write("error/d", "Control file not found");
+
+        let summary = ErrorSummary::from_output(output.path()).unwrap();
+        assert_eq!(summary.total(), 4);
+        let kinds = summary.kinds();
+        assert_eq!(
+            kinds.keys().collect::<Vec<_>>(),
+            ["config", "redirect", UNKNOWN_KIND]
+        );
+        assert_eq!(kinds["redirect"].count(), 2);
+        assert_eq!(kinds["redirect"].phases()[&Phase::Redirect], 2);
+        assert_eq!(kinds["redirect"].pkgs, ["a", "b"]);
+        assert!(kinds["config"].phases().is_empty());
+        assert_eq!(kinds[UNKNOWN_KIND].pkgs, ["d"]);
+    }
+}
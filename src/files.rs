@@ -0,0 +1,418 @@
+//! Matching debian packages to nixpkgs attributes through the files they ship,
+//! by relating a debian `Contents` index to a listing of the nix store paths.
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use control_file::map::NixTarget;
+
+use crate::error::DebNixError;
+use crate::nix::index::{is_nix_index, NixIndexFiles};
+use crate::nix::NIX_ATTRIBUTES_NEW;
+
+/// A file that identifies a package independent of its name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FileKey {
+    /// A pkg-config file: `xcb` for `lib/pkgconfig/xcb.pc`
+    PkgConfig(String),
+    /// A header, relative to the include directory: `xcb/xcb.h`
+    Header(String),
+    /// A shared library: `libxcb.so.1`
+    SharedLib(String),
+}
+
+impl FileKey {
+    /// Extracts the key out of a path relative to a prefix:
+    /// `lib/pkgconfig/xcb.pc`, `include/xcb/xcb.h`, `lib/x86_64-linux-gnu/libxcb.so.1`
+    /// Headers in the multiarch include directory of debian
+    /// (`include/x86_64-linux-gnu/gmp.h`) are keyed without the triplet.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.trim_start_matches('/');
+        let (parent, file_name) = path.rsplit_once('/')?;
+        if let Some(name) = file_name.strip_suffix(".pc") {
+            if parent.ends_with("pkgconfig") {
+                return Some(FileKey::PkgConfig(name.into()));
+            }
+        }
+        if let Some(header) = path.strip_prefix("include/") {
+            let header = match header.split_once('/') {
+                Some((triplet, header)) if is_multiarch_triplet(triplet) => header,
+                _ => header,
+            };
+            return Some(FileKey::Header(header.into()));
+        }
+        if path.starts_with("lib/") && file_name.starts_with("lib") && file_name.contains(".so") {
+            return Some(FileKey::SharedLib(file_name.into()));
+        }
+        None
+    }
+
+    /// Extracts the key out of a path inside of a debian package.
+    pub fn from_debian_path(path: &str) -> Option<Self> {
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+        Self::from_path(path.strip_prefix("usr/").unwrap_or(path))
+    }
+
+    /// Extracts the key out of a path inside of a nix store path,
+    /// paths that are not inside of the store are taken as relative.
+    pub fn from_nix_path(path: &str) -> Option<Self> {
+        let path = match path.strip_prefix("/nix/store/") {
+            Some(store_path) => store_path.split_once('/')?.1,
+            None => path,
+        };
+        Self::from_path(path)
+    }
+
+    /// How much a single shared file says about two packages being the same.
+    /// Pkg-config names are the most distinct.
+    fn weight(&self) -> usize {
+        match self {
+            FileKey::PkgConfig(_) => 4,
+            FileKey::SharedLib(_) => 2,
+            FileKey::Header(_) => 1,
+        }
+    }
+}
+
+/// A debian multiarch triplet: `x86_64-linux-gnu`, `arm-linux-gnueabihf`, `i386-gnu`
+fn is_multiarch_triplet(component: &str) -> bool {
+    component.contains("-linux-") || component.contains("-kfreebsd-") || component.ends_with("-gnu")
+}
+
+/// Opens a possibly gzip compressed index file.
+pub(crate) fn open_index(location: &Path) -> Result<Box<dyn BufRead>, DebNixError> {
+    let file = File::open(location)
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+    let reader: Box<dyn Read> = if location.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+/// The relevant files of every debian package,
+/// read from a debian `Contents` index:
+/// `usr/lib/x86_64-linux-gnu/pkgconfig/xcb.pc    libdevel/libxcb1-dev`
+#[derive(Debug, Default)]
+pub struct ContentsIndex {
+    files: HashMap<String, Vec<FileKey>>,
+    /// The debian packages, that ship a file, sorted.
+    owners: HashMap<FileKey, Vec<String>>,
+}
+
+impl ContentsIndex {
+    /// Reads a local, possibly gzip compressed, `Contents-<arch>` file.
+    pub fn open(location: &Path) -> Result<Self, DebNixError> {
+        let mut files: HashMap<String, Vec<FileKey>> = HashMap::new();
+        for line in open_index(location)?.lines() {
+            let line = line?;
+            let Some((path, locations)) = line.trim_end().rsplit_once(char::is_whitespace) else {
+                continue;
+            };
+            let Some(key) = FileKey::from_debian_path(path.trim_end()) else {
+                continue;
+            };
+            for location in locations.split(',') {
+                let pkg = location.rsplit_once('/').map_or(location, |(_, pkg)| pkg);
+                files.entry(pkg.into()).or_default().push(key.clone());
+            }
+        }
+        let mut owners: HashMap<FileKey, Vec<String>> = HashMap::new();
+        for (pkg, keys) in &files {
+            for key in keys {
+                owners.entry(key.clone()).or_default().push(pkg.clone());
+            }
+        }
+        for pkgs in owners.values_mut() {
+            pkgs.sort_unstable();
+            pkgs.dedup();
+        }
+        Ok(Self { files, owners })
+    }
+
+    /// The relevant files, that a debian package ships.
+    pub fn files(&self, pkg: &str) -> &[FileKey] {
+        self.files.get(pkg).map_or(&[], Vec::as_slice)
+    }
+
+    /// The debian packages, that ship a file.
+    pub fn owners(&self, key: &FileKey) -> Vec<&str> {
+        self.owners
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// A nixpkgs attribute output, that provides a file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NixFileProvider {
+    attr: String,
+    output: String,
+}
+
+impl NixFileProvider {
+    pub fn new(attr: impl Into<String>, output: impl Into<String>) -> Self {
+        Self {
+            attr: attr.into(),
+            output: output.into(),
+        }
+    }
+
+    /// Splits a known output off of an attribute path: `xorg.libxcb.dev`.
+    pub fn from_attr_path(attr_path: &str) -> Self {
+        let target = NixTarget::from_attr_path(attr_path, None);
+        Self::new(target.attr(), target.output().unwrap_or("out"))
+    }
+
+    pub fn attr(&self) -> &str {
+        self.attr.as_ref()
+    }
+
+    pub fn output(&self) -> &str {
+        self.output.as_ref()
+    }
+
+    /// The attribute path of the output, the default output is left implicit.
+    pub fn target(&self) -> String {
+        let output = (self.output != "out").then(|| self.output.clone());
+        NixTarget::new(self.attr.clone(), output, None).attr_path()
+    }
+}
+
+/// An index of the files inside of nixpkgs.
+pub trait NixFileIndex {
+    /// The attribute outputs, that provide a file.
+    fn providers(&self, key: &FileKey) -> Vec<NixFileProvider>;
+}
+
+/// A locally built listing of the files in the nix store,
+/// one file per line, prefixed by the attribute output that provides it:
+/// `xorg.libxcb.dev /nix/store/<hash>-libxcb-1.16-dev/lib/pkgconfig/xcb.pc`
+/// Additional columns in between are ignored, so `nix-locate` output can be used as well.
+#[derive(Debug, Default)]
+pub struct StorePathListing {
+    providers: HashMap<FileKey, Vec<NixFileProvider>>,
+}
+
+impl StorePathListing {
+    pub fn open(location: &Path) -> Result<Self, DebNixError> {
+        let mut providers: HashMap<FileKey, Vec<NixFileProvider>> = HashMap::new();
+        for line in open_index(location)?.lines() {
+            let line = line?;
+            let mut columns = line.split_whitespace();
+            let (Some(attr_path), Some(path)) = (columns.next(), columns.last()) else {
+                continue;
+            };
+            let Some(key) = FileKey::from_nix_path(path) else {
+                continue;
+            };
+            let attr_path = attr_path.trim_start_matches('(').trim_end_matches(')');
+            let provider = NixFileProvider::from_attr_path(attr_path);
+            let entry = providers.entry(key).or_default();
+            if !entry.contains(&provider) {
+                entry.push(provider);
+            }
+        }
+        Ok(Self { providers })
+    }
+}
+
+impl NixFileIndex for StorePathListing {
+    fn providers(&self, key: &FileKey) -> Vec<NixFileProvider> {
+        self.providers.get(key).cloned().unwrap_or_default()
+    }
+}
+
+/// Opens either a nix-index database, or a store path listing.
+pub fn open_nix_file_index(
+    location: &Path,
+) -> Result<Box<dyn NixFileIndex + Send + Sync>, DebNixError> {
+    if is_nix_index(location) {
+        Ok(Box::new(NixIndexFiles::open(location)?))
+    } else {
+        Ok(Box::new(StorePathListing::open(location)?))
+    }
+}
+
+/// Matches debian packages to nixpkgs attributes,
+/// through the files that both of them ship.
+pub struct FileMatcher {
+    contents: ContentsIndex,
+    nix_files: Box<dyn NixFileIndex + Send + Sync>,
+}
+
+impl std::fmt::Debug for FileMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileMatcher")
+            .field("contents", &self.contents.files.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileMatcher {
+    pub fn new(contents: ContentsIndex, nix_files: Box<dyn NixFileIndex + Send + Sync>) -> Self {
+        Self {
+            contents,
+            nix_files,
+        }
+    }
+
+    pub fn contents(&self) -> &ContentsIndex {
+        &self.contents
+    }
+
+    pub fn nix_files(&self) -> &(dyn NixFileIndex + Send + Sync) {
+        self.nix_files.as_ref()
+    }
+
+    /// Finds the attribute output, that provides the most of the
+    /// relevant files of a debian package.
+    pub fn match_pkg(&self, deb: &str) -> Option<NixFileProvider> {
+        let mut votes: HashMap<NixFileProvider, usize> = HashMap::new();
+        for key in self.contents.files(deb) {
+            for provider in self.nix_files.providers(key) {
+                *votes.entry(provider).or_default() += key.weight();
+            }
+        }
+        // Ties are broken by the attribute path, in order to stay deterministic.
+        votes
+            .into_iter()
+            .max_by(|(a, a_votes), (b, b_votes)| a_votes.cmp(b_votes).then_with(|| b.cmp(a)))
+            .map(|(provider, _)| provider)
+    }
+}
+
+/// The pname of an attribute, in order to relate it to the inputs of a derivation.
+pub(crate) fn pname_of(attr: &str) -> Option<&str> {
+    NIX_ATTRIBUTES_NEW
+        .get(attr)
+        .map(|attributes| attributes.pname())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const CONTENTS: &str = "\
+usr/include/gmp.h                                       libdevel/libgmp-dev
+usr/include/x86_64-linux-gnu/gmp.h                      libdevel/libgmp-dev
+usr/include/xcb/xcb.h                                   libdevel/libxcb1-dev
+usr/lib/x86_64-linux-gnu/libxcb.so.1                    libs/libxcb1
+usr/lib/x86_64-linux-gnu/pkgconfig/xcb.pc               libdevel/libxcb1-dev
+usr/share/doc/libxcb1/copyright                         libs/libxcb1
+usr/lib/x86_64-linux-gnu/libX11.so.6                    libs/libx11-6,oldlibs/libx11-6-compat
+";
+
+    #[test]
+    fn file_keys() {
+        assert_eq!(
+            FileKey::from_path("lib/pkgconfig/xcb.pc"),
+            Some(FileKey::PkgConfig("xcb".into()))
+        );
+        assert_eq!(
+            FileKey::from_path("share/pkgconfig/xproto.pc"),
+            Some(FileKey::PkgConfig("xproto".into()))
+        );
+        assert_eq!(
+            FileKey::from_path("include/xcb/xcb.h"),
+            Some(FileKey::Header("xcb/xcb.h".into()))
+        );
+        assert_eq!(
+            FileKey::from_path("lib/x86_64-linux-gnu/libxcb.so.1"),
+            Some(FileKey::SharedLib("libxcb.so.1".into()))
+        );
+        assert_eq!(FileKey::from_path("share/doc/libxcb1/copyright"), None);
+        assert_eq!(FileKey::from_path("bin/i3"), None);
+    }
+
+    #[test]
+    fn multiarch_headers_match_nix_headers() {
+        let debian = FileKey::from_debian_path("usr/include/x86_64-linux-gnu/gmp.h");
+        let nix = FileKey::from_nix_path("/nix/store/0m2mjrwm-gmp-6.3.0-dev/include/gmp.h");
+        assert_eq!(debian, Some(FileKey::Header("gmp.h".into())));
+        assert_eq!(debian, nix);
+        assert_eq!(
+            FileKey::from_debian_path("./usr/include/arm-linux-gnueabihf/openssl/opensslconf.h"),
+            Some(FileKey::Header("openssl/opensslconf.h".into()))
+        );
+        // only the first component can be a triplet
+        assert_eq!(
+            FileKey::from_debian_path("usr/include/glib-2.0/glib.h"),
+            Some(FileKey::Header("glib-2.0/glib.h".into()))
+        );
+    }
+
+    #[test]
+    fn providers_from_attr_paths() {
+        let provider = NixFileProvider::from_attr_path("xorg.libxcb.dev");
+        assert_eq!((provider.attr(), provider.output()), ("xorg.libxcb", "dev"));
+        assert_eq!(provider.target(), "xorg.libxcb.dev");
+        let provider = NixFileProvider::from_attr_path("python3Packages.six");
+        assert_eq!(
+            (provider.attr(), provider.output()),
+            ("python3Packages.six", "out")
+        );
+        assert_eq!(provider.target(), "python3Packages.six");
+    }
+
+    #[test]
+    fn contents_index() {
+        let dir = TempDir::new("files-contents");
+        let contents = ContentsIndex::open(&dir.write("Contents-amd64", CONTENTS)).unwrap();
+        assert_eq!(
+            contents.files("libxcb1-dev"),
+            &[
+                FileKey::Header("xcb/xcb.h".into()),
+                FileKey::PkgConfig("xcb".into())
+            ]
+        );
+        assert!(contents.files("libfoo").is_empty());
+        assert_eq!(
+            contents.owners(&FileKey::Header("gmp.h".into())),
+            vec!["libgmp-dev"]
+        );
+        assert_eq!(
+            contents.owners(&FileKey::SharedLib("libX11.so.6".into())),
+            vec!["libx11-6", "libx11-6-compat"]
+        );
+        assert!(contents
+            .owners(&FileKey::SharedLib("libfoo.so.1".into()))
+            .is_empty());
+    }
+
+    #[test]
+    fn match_pkg_by_weighted_files() {
+        let dir = TempDir::new("files-match");
+        let index = dir.write("Contents-amd64", CONTENTS);
+        let listing = dir.write(
+            "listing",
+            "\
+xorg.libxcb.dev /nix/store/0m2mjrwm-libxcb-1.16-dev/lib/pkgconfig/xcb.pc
+xorg.libxcb.dev /nix/store/0m2mjrwm-libxcb-1.16-dev/include/xcb/xcb.h
+xcb-proto /nix/store/0m2mjrwm-xcb-proto-1.16/include/xcb/xcb.h
+gmp.dev /nix/store/0m2mjrwm-gmp-6.3.0-dev/include/gmp.h
+",
+        );
+        let matcher = FileMatcher::new(
+            ContentsIndex::open(&index).unwrap(),
+            Box::new(StorePathListing::open(&listing).unwrap()),
+        );
+        assert_eq!(
+            matcher.match_pkg("libxcb1-dev"),
+            Some(NixFileProvider::new("xorg.libxcb", "dev"))
+        );
+        assert_eq!(
+            matcher.match_pkg("libgmp-dev"),
+            Some(NixFileProvider::new("gmp", "dev"))
+        );
+        assert_eq!(matcher.match_pkg("libfoo"), None);
+    }
+}
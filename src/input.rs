@@ -0,0 +1,274 @@
+//! The sources of the pkgs, that are discovered.
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, Read},
+    path::Path,
+    str::FromStr,
+};
+
+use control_file::{infrastructure::Infrastructure, ControlFile};
+
+use crate::deb::read_popcon_str;
+use crate::error::DebNixError;
+use crate::files::open_index;
+use crate::limits::HTTP;
+
+/// Where the pkgs for discovery come from:
+/// `popcon:<path-or-url>`, `list:<path>`, `stdin`, `sources:<path>`, `control-dir:<dir>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoverInput {
+    /// A popularity contest ranking, either a local copy, or downloaded from a url:
+    /// `https://popcon.debian.org/by_inst`
+    Popcon(String),
+    /// A newline separated list of pkgs, `#` starts a comment.
+    List(String),
+    /// A newline separated list of pkgs, read from stdin.
+    Stdin,
+    /// Every source pkg of a, possibly gzip compressed, debian `Sources` index.
+    Sources(String),
+    /// The dependencies of every control file inside of a directory,
+    /// without debian infrastructure.
+    ControlDir(String),
+}
+
+impl Default for DiscoverInput {
+    fn default() -> Self {
+        DiscoverInput::Popcon("./test/popcon.csv".into())
+    }
+}
+
+impl FromStr for DiscoverInput {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input == "stdin" || input == "-" {
+            return Ok(DiscoverInput::Stdin);
+        }
+        let Some((kind, location)) = input.split_once(':') else {
+            return Err(format!(
+                "Expected <kind>:<location>, or stdin, where kind is one of \
+                 popcon, list, sources, control-dir: {input}"
+            ));
+        };
+        let location = location.to_owned();
+        match kind {
+            "popcon" => Ok(DiscoverInput::Popcon(location)),
+            "list" => Ok(DiscoverInput::List(location)),
+            "sources" => Ok(DiscoverInput::Sources(location)),
+            "control-dir" => Ok(DiscoverInput::ControlDir(location)),
+            _ => Err(format!("Unknown kind of discovery input: {kind}")),
+        }
+    }
+}
+
+impl DiscoverInput {
+    /// Reads the pkgs in their original order, duplicates are left out.
+    pub fn read(&self, infrastructure: &Infrastructure) -> Result<Vec<String>, DebNixError> {
+        let pkgs = match self {
+            DiscoverInput::Popcon(location) => {
+                let contents =
+                    if location.starts_with("https://") || location.starts_with("http://") {
+                        let _permit = HTTP.acquire();
+                        reqwest::blocking::get(location)?
+                            .error_for_status()?
+                            .text()?
+                    } else {
+                        fs::read_to_string(location)
+                            .map_err(|e| DebNixError::IoPath(format!("{e}: {location}")))?
+                    };
+                read_popcon_str(&contents)
+            }
+            DiscoverInput::List(location) => read_list(
+                fs::read_to_string(location)
+                    .map_err(|e| DebNixError::IoPath(format!("{e}: {location}")))?,
+            ),
+            DiscoverInput::Stdin => {
+                let mut contents = String::new();
+                io::stdin().lock().read_to_string(&mut contents)?;
+                read_list(contents)
+            }
+            DiscoverInput::Sources(location) => read_sources(Path::new(location))?,
+            DiscoverInput::ControlDir(location) => {
+                let (pkgs, _) = infrastructure.partition(read_control_dir(Path::new(location))?);
+                pkgs
+            }
+        };
+        let mut seen = HashSet::new();
+        Ok(pkgs
+            .into_iter()
+            .filter(|pkg| seen.insert(pkg.clone()))
+            .collect())
+    }
+}
+
+fn read_list(contents: String) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// The source pkgs of a `Sources` index: `Package: libxcb`
+fn read_sources(location: &Path) -> Result<Vec<String>, DebNixError> {
+    let mut pkgs = vec![];
+    for line in open_index(location)?.lines() {
+        let line = line?;
+        if let Some(pkg) = line.strip_prefix("Package:") {
+            pkgs.push(pkg.trim().to_owned());
+        }
+    }
+    Ok(pkgs)
+}
+
+/// The dependencies of every control file inside of a directory, searched recursively.
+/// Control files are named `control`, or start with `control` (`control-1.15-1`).
+fn read_control_dir(location: &Path) -> Result<Vec<String>, DebNixError> {
+    let mut entries = fs::read_dir(location)
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    let mut deps = vec![];
+    for path in entries {
+        if path.is_dir() {
+            deps.extend(read_control_dir(&path)?);
+        } else if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("control"))
+        {
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| DebNixError::IoPath(format!("{e}: {}", path.display())))?;
+            match ControlFile::from_str(&contents).and_then(|control| control.get_dependencies()) {
+                Ok(mut control_deps) => {
+                    control_deps.sort();
+                    deps.extend(control_deps);
+                }
+                Err(e) => error!("Reading control file {}: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(deps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn read(input: &str) -> Vec<String> {
+        DiscoverInput::from_str(input)
+            .unwrap()
+            .read(&Infrastructure::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn inputs_from_str() {
+        let cases = [
+            ("stdin", DiscoverInput::Stdin),
+            ("-", DiscoverInput::Stdin),
+            (
+                "popcon:https://popcon.debian.org/by_inst",
+                DiscoverInput::Popcon("https://popcon.debian.org/by_inst".into()),
+            ),
+            ("list:pkgs.txt", DiscoverInput::List("pkgs.txt".into())),
+            (
+                "sources:Sources.gz",
+                DiscoverInput::Sources("Sources.gz".into()),
+            ),
+            (
+                "control-dir:./controls",
+                DiscoverInput::ControlDir("./controls".into()),
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(DiscoverInput::from_str(input).unwrap(), expected, "{input}");
+        }
+        assert!(DiscoverInput::from_str("pkgs.txt").is_err());
+        assert!(DiscoverInput::from_str("file:pkgs.txt")
+            .unwrap_err()
+            .contains("file"));
+    }
+
+    #[test]
+    fn list_skips_comments_and_duplicates() {
+        let dir = TempDir::new("input-list");
+        let list = dir.write(
+            "pkgs.txt",
+            "# the most popular pkgs\ni3\n\n  libxcb  # a comment\ni3\ndebhelper\n",
+        );
+        assert_eq!(
+            read(&format!("list:{}", list.display())),
+            ["i3", "libxcb", "debhelper"]
+        );
+        assert!(
+            DiscoverInput::List(dir.path().join("missing").display().to_string())
+                .read(&Infrastructure::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn sources_names_the_source_pkgs() {
+        let dir = TempDir::new("input-sources");
+        let sources = dir.write(
+            "Sources",
+            "\
+Package: libxcb
+Binary: libxcb1, libxcb1-dev
+Version: 1.15-1
+
+Package: i3-wm
+Binary: i3, i3-wm
+Version: 4.22-2
+
+Package: libxcb
+Version: 1.17-1
+",
+        );
+        assert_eq!(
+            read(&format!("sources:{}", sources.display())),
+            ["libxcb", "i3-wm"]
+        );
+    }
+
+    #[test]
+    fn control_dir_without_infrastructure() {
+        let dir = TempDir::new("input-control-dir");
+        dir.write(
+            "i3/control",
+            "\
+Source: i3-wm
+Build-Depends: debhelper-compat (= 13), libxcb1-dev, libxkbcommon-dev
+
+Package: i3-wm
+Depends: libxcb1
+",
+        );
+        dir.write(
+            "libxcb/control-1.15-1",
+            "\
+Source: libxcb
+Build-Depends: dh-python, libxau-dev, libxcb1-dev
+",
+        );
+        dir.write("libxcb/changelog", "Build-Depends: libchangelog\n");
+        let input = format!("control-dir:{}", dir.path().display());
+        assert_eq!(
+            read(&input),
+            ["libxcb1", "libxcb1-dev", "libxkbcommon-dev", "libxau-dev"]
+        );
+        let infrastructure = Infrastructure::with_overrides(["libxau*"], ["dh-python"]);
+        assert_eq!(
+            DiscoverInput::from_str(&input)
+                .unwrap()
+                .read(&infrastructure)
+                .unwrap(),
+            ["libxcb1", "libxcb1-dev", "libxkbcommon-dev", "dh-python"]
+        );
+    }
+}
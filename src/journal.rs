@@ -0,0 +1,452 @@
+//! The journal of a discovery sweep, stored as `journal.json` in the output directory.
+//! It records the state of every pkg, so that an interrupted, or partially failed
+//! sweep can be continued independent of the ordering of its input.
+//! Changes during a sweep are appended to `journal.log`, which is folded
+//! back into `journal.json`, when the journal is opened, or saved.
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::DebNixError;
+use crate::failures::ErrorRecord;
+use crate::state::to_canonical_json;
+
+/// The state of a single pkg in the sweep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// Not yet discovered, or interrupted while it was discovered.
+    Pending,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    state: JobState,
+    /// The error of the last attempt, if it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    started: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finished: Option<DateTime<Utc>>,
+    /// The depth in the dependency closure of a recursive discovery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depth: Option<usize>,
+}
+
+impl JournalEntry {
+    fn pending() -> Self {
+        Self {
+            state: JobState::Pending,
+            reason: None,
+            attempts: 0,
+            started: None,
+            finished: None,
+            depth: None,
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        self.state
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+/// A single line of `journal.log`, the new entry of a pkg.
+#[derive(Debug, Serialize, Deserialize)]
+struct LogRecord {
+    pkg: String,
+    entry: JournalEntry,
+}
+
+/// `{pkg: entry}`, every change is appended to the log.
+#[derive(Debug, Default)]
+pub struct Journal {
+    location: PathBuf,
+    entries: BTreeMap<String, JournalEntry>,
+    /// The log, once the first change was appended to it.
+    log: Option<File>,
+}
+
+impl Journal {
+    /// The name of the journal inside of the output directory.
+    const FILE_NAME: &'static str = "journal.json";
+    /// The name of the log of changes, that are not yet part of the journal.
+    const LOG_NAME: &'static str = "journal.log";
+
+    /// Opens the journal of an output directory, or starts a new one.
+    /// The changes of the log are replayed and compacted into the journal.
+    pub fn open(output: &Path) -> Result<Self, DebNixError> {
+        let location = output.join(Self::FILE_NAME);
+        let entries = if location.exists() {
+            let contents = fs::read_to_string(&location)
+                .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+            serde_json::from_str(&contents)?
+        } else {
+            BTreeMap::new()
+        };
+        let mut journal = Self {
+            location,
+            entries,
+            log: None,
+        };
+        if journal.replay()? {
+            journal.save()?;
+        }
+        Ok(journal)
+    }
+
+    /// If the file belongs to the journal of an output directory.
+    pub fn is_journal(location: &Path) -> bool {
+        location
+            .file_name()
+            .is_some_and(|name| name == Self::FILE_NAME || name == Self::LOG_NAME)
+    }
+
+    fn log_location(&self) -> PathBuf {
+        self.location.with_file_name(Self::LOG_NAME)
+    }
+
+    /// Applies the records of the log, returns if there were any.
+    /// A truncated last record of an interrupted write is skipped.
+    fn replay(&mut self) -> Result<bool, DebNixError> {
+        let location = self.log_location();
+        if !location.exists() {
+            return Ok(false);
+        }
+        let log = File::open(&location)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+        for line in BufReader::new(log).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<LogRecord>(&line) {
+                Ok(record) => {
+                    self.entries.insert(record.pkg, record.entry);
+                }
+                Err(e) => {
+                    warn!("Skipping the rest of {}: {}", location.display(), e);
+                    break;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Appends the current entry of a pkg to the log.
+    fn append(&mut self, pkg: &str) -> Result<(), DebNixError> {
+        let Some(entry) = self.entries.get(pkg) else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_string(&LogRecord {
+            pkg: pkg.into(),
+            entry: entry.clone(),
+        })?;
+        line.push('\n');
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => {
+                let location = self.log_location();
+                let log = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&location)
+                    .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+                self.log.insert(log)
+            }
+        };
+        log.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Adds pkgs, that are not yet part of the journal.
+    /// Pkgs of sweeps from before the journal are recognized by their
+    /// output file, or error record.
+    pub fn add(&mut self, pkgs: &[String], output: &Path) {
+        for pkg in pkgs {
+            if !self.entries.contains_key(pkg) {
+                self.entries
+                    .insert(pkg.clone(), Self::former_entry(pkg, output));
+            }
+        }
+    }
+
+    /// The entry of a pkg, that is not yet part of the journal,
+    /// done or failed if a former sweep left an output file, or an error record.
+    fn former_entry(pkg: &str, output: &Path) -> JournalEntry {
+        let mut entry = JournalEntry::pending();
+        if output.join(format!("{pkg}-debnix.json")).exists() {
+            entry.state = JobState::Done;
+        } else if let Some(record) = [
+            ErrorRecord::location(&output.join("error"), pkg),
+            output.join("error").join(pkg),
+        ]
+        .iter()
+        .find_map(|location| ErrorRecord::open(location).ok())
+        {
+            entry.state = JobState::Failed;
+            entry.reason = Some(record.message().into());
+        }
+        entry
+    }
+
+    /// Adds a dependency of a recursive discovery to the log,
+    /// returns if it is pending and wasn't part of the journal.
+    /// Dependencies, that a former sweep discovered, are recognized like in [`Journal::add`].
+    pub fn enqueue(&mut self, pkg: &str, depth: usize) -> Result<bool, DebNixError> {
+        if self.entries.contains_key(pkg) {
+            return Ok(false);
+        }
+        let output = self.location.parent().unwrap_or(Path::new("."));
+        let mut entry = Self::former_entry(pkg, output);
+        entry.depth = Some(depth);
+        let pending = entry.state == JobState::Pending;
+        self.entries.insert(pkg.into(), entry);
+        self.append(pkg)?;
+        Ok(pending)
+    }
+
+    /// The depth of a pkg in the dependency closure, explicit inputs have a depth of `0`.
+    pub fn depth(&self, pkg: &str) -> usize {
+        self.get(pkg).and_then(|entry| entry.depth).unwrap_or(0)
+    }
+
+    /// Marks the failed pkgs as pending, so that they are attempted again.
+    pub fn retry_failed(&mut self) {
+        for entry in self.entries.values_mut() {
+            if entry.state == JobState::Failed {
+                entry.state = JobState::Pending;
+            }
+        }
+    }
+
+    pub fn get(&self, pkg: &str) -> Option<&JournalEntry> {
+        self.entries.get(pkg)
+    }
+
+    pub fn is_pending(&self, pkg: &str) -> bool {
+        self.get(pkg)
+            .map_or(true, |entry| entry.state == JobState::Pending)
+    }
+
+    /// The pending pkgs of the journal.
+    pub fn pending(&self) -> impl Iterator<Item = &String> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.state == JobState::Pending)
+            .map(|(pkg, _)| pkg)
+    }
+
+    /// Records the start of an attempt.
+    pub fn start(&mut self, pkg: &str) -> Result<(), DebNixError> {
+        let entry = self
+            .entries
+            .entry(pkg.into())
+            .or_insert_with(JournalEntry::pending);
+        entry.state = JobState::Pending;
+        entry.attempts += 1;
+        entry.started = Some(Utc::now());
+        entry.finished = None;
+        self.append(pkg)
+    }
+
+    /// Records the outcome of an attempt.
+    pub fn finish(&mut self, pkg: &str, result: Result<(), String>) -> Result<(), DebNixError> {
+        let entry = self
+            .entries
+            .entry(pkg.into())
+            .or_insert_with(JournalEntry::pending);
+        match result {
+            Ok(()) => {
+                entry.state = JobState::Done;
+                entry.reason = None;
+            }
+            Err(reason) => {
+                entry.state = JobState::Failed;
+                entry.reason = Some(reason);
+            }
+        }
+        entry.finished = Some(Utc::now());
+        self.append(pkg)
+    }
+
+    /// Records the attempts, that are still running, as interrupted.
+    /// They stay pending, so that they are attempted again on resumption.
+    pub fn interrupt_running(&mut self) {
+        for entry in self.entries.values_mut() {
+            if entry.state == JobState::Pending
+                && entry.started.is_some()
+                && entry.finished.is_none()
+            {
+                entry.reason = Some("interrupted".into());
+                entry.finished = Some(Utc::now());
+            }
+        }
+    }
+
+    /// Writes the journal through a temporary file,
+    /// so that an interruption never leaves a truncated journal behind.
+    /// The log is removed afterwards, since the journal contains all of its changes.
+    pub fn save(&mut self) -> Result<(), DebNixError> {
+        let temporary = self.location.with_extension("json.tmp");
+        fs::write(&temporary, to_canonical_json(&self.entries)?)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {}", temporary.display())))?;
+        fs::rename(&temporary, &self.location)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {}", self.location.display())))?;
+        self.log = None;
+        let log = self.log_location();
+        if log.exists() {
+            fs::remove_file(&log)
+                .map_err(|e| DebNixError::IoPath(format!("{e}: {}", log.display())))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn pkgs(pkgs: &[&str]) -> Vec<String> {
+        pkgs.iter().map(|pkg| String::from(*pkg)).collect()
+    }
+
+    #[test]
+    fn add_recognizes_former_sweeps() {
+        let output = TempDir::new("journal-add");
+        output.write("done-debnix.json", "{}");
+        output.write("error/legacy", "Control file not found");
+        let mut journal = Journal::open(output.path()).unwrap();
+        journal.add(&pkgs(&["done", "legacy", "new"]), output.path());
+        assert_eq!(journal.get("done").unwrap().state(), JobState::Done);
+        let legacy = journal.get("legacy").unwrap();
+        assert_eq!(legacy.state(), JobState::Failed);
+        assert_eq!(legacy.reason(), Some("Control file not found"));
+        assert_eq!(journal.get("new").unwrap().state(), JobState::Pending);
+        assert_eq!(journal.pending().collect::<Vec<_>>(), ["new"]);
+
+        // known pkgs are kept as they are
+        journal.finish("new", Ok(())).unwrap();
+        journal.add(&pkgs(&["new"]), output.path());
+        assert_eq!(journal.get("new").unwrap().state(), JobState::Done);
+    }
+
+    #[test]
+    fn retry_failed_and_is_pending() {
+        let output = TempDir::new("journal-retry");
+        let mut journal = Journal::open(output.path()).unwrap();
+        journal.add(&pkgs(&["failed", "done"]), output.path());
+        journal.start("failed").unwrap();
+        journal.finish("failed", Err("timeout".into())).unwrap();
+        journal.start("done").unwrap();
+        journal.finish("done", Ok(())).unwrap();
+        assert!(!journal.is_pending("failed"));
+        assert!(!journal.is_pending("done"));
+        // unknown pkgs haven't been attempted yet
+        assert!(journal.is_pending("unknown"));
+
+        journal.retry_failed();
+        assert!(journal.is_pending("failed"));
+        assert!(!journal.is_pending("done"));
+        let failed = journal.get("failed").unwrap();
+        assert_eq!(failed.attempts(), 1);
+        assert_eq!(failed.reason(), Some("timeout"));
+    }
+
+    #[test]
+    fn interrupt_running() {
+        let output = TempDir::new("journal-interrupt");
+        let mut journal = Journal::open(output.path()).unwrap();
+        journal.add(&pkgs(&["running", "waiting", "done"]), output.path());
+        journal.start("running").unwrap();
+        journal.start("done").unwrap();
+        journal.finish("done", Ok(())).unwrap();
+        journal.interrupt_running();
+        let running = journal.get("running").unwrap();
+        assert_eq!(running.state(), JobState::Pending);
+        assert_eq!(running.reason(), Some("interrupted"));
+        assert!(journal.get("waiting").unwrap().reason().is_none());
+        assert_eq!(journal.get("done").unwrap().state(), JobState::Done);
+        assert_eq!(
+            journal.pending().collect::<Vec<_>>(),
+            ["running", "waiting"]
+        );
+    }
+
+    #[test]
+    fn log_is_replayed_and_compacted() {
+        let output = TempDir::new("journal-log");
+        let mut journal = Journal::open(output.path()).unwrap();
+        journal.add(&pkgs(&["a", "b"]), output.path());
+        journal.save().unwrap();
+        journal.start("a").unwrap();
+        journal.finish("a", Ok(())).unwrap();
+        journal.start("b").unwrap();
+        drop(journal);
+        // an interrupted write
+        OpenOptions::new()
+            .append(true)
+            .open(output.path().join(Journal::LOG_NAME))
+            .and_then(|mut log| log.write_all(b"{\"pkg\": \"b\", \"ent"))
+            .unwrap();
+
+        let journal = Journal::open(output.path()).unwrap();
+        assert_eq!(journal.get("a").unwrap().state(), JobState::Done);
+        let b = journal.get("b").unwrap();
+        assert_eq!(b.state(), JobState::Pending);
+        assert_eq!(b.attempts(), 1);
+        assert!(!output.path().join(Journal::LOG_NAME).exists());
+        let compacted = Journal::open(output.path()).unwrap();
+        assert_eq!(compacted.entries, journal.entries);
+    }
+
+    #[test]
+    fn enqueued_dependencies_survive_without_a_save() {
+        let output = TempDir::new("journal-enqueue");
+        output.write("done-debnix.json", "{}");
+        let record = ErrorRecord::new("failed", &DebNixError::Config("broken".into()));
+        fs::create_dir_all(output.path().join("error")).unwrap();
+        record.write(&output.path().join("error")).unwrap();
+        let mut journal = Journal::open(output.path()).unwrap();
+        journal.add(&pkgs(&["root"]), output.path());
+        journal.save().unwrap();
+        assert!(journal.enqueue("dep", 1).unwrap());
+        assert!(!journal.enqueue("dep", 2).unwrap());
+        assert!(!journal.enqueue("root", 1).unwrap());
+        // former sweeps discovered them already
+        assert!(!journal.enqueue("done", 1).unwrap());
+        assert!(!journal.enqueue("failed", 1).unwrap());
+        drop(journal);
+
+        let journal = Journal::open(output.path()).unwrap();
+        assert!(journal.is_pending("dep"));
+        assert_eq!(journal.depth("dep"), 1);
+        assert_eq!(journal.depth("root"), 0);
+        assert_eq!(journal.get("done").unwrap().state(), JobState::Done);
+        assert_eq!(journal.depth("done"), 1);
+        let failed = journal.get("failed").unwrap();
+        assert_eq!(failed.state(), JobState::Failed);
+        assert!(failed
+            .reason()
+            .is_some_and(|reason| reason.contains("broken")));
+    }
+}
@@ -0,0 +1,73 @@
+//! Limits of the concurrent operations, that are shared by all workers.
+use std::sync::{Condvar, Mutex};
+
+/// Limits the amount of concurrent operations of a kind.
+#[derive(Debug)]
+pub struct Semaphore {
+    permits: Mutex<Permits>,
+    available: Condvar,
+}
+
+#[derive(Debug)]
+struct Permits {
+    used: usize,
+    limit: usize,
+}
+
+/// Gives its permit back, once it is dropped.
+pub struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            permits: Mutex::new(Permits { used: 0, limit }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Changes the limit, at least a single permit is always available.
+    pub fn set_limit(&self, limit: usize) {
+        self.permits.lock().unwrap().limit = limit.max(1);
+        self.available.notify_all();
+    }
+
+    /// Blocks until a permit is available.
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while permits.used >= permits.limit {
+            permits = self.available.wait(permits).unwrap();
+        }
+        permits.used += 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.permits.lock().unwrap().used -= 1;
+        self.0.available.notify_one();
+    }
+}
+
+/// Limits the concurrent requests to `tracker.debian.org` and `sources.debian.org`.
+pub static HTTP: Semaphore = Semaphore::new(usize::MAX);
+/// Limits the concurrent `nix` subprocesses.
+pub static NIX: Semaphore = Semaphore::new(usize::MAX);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_given_back() {
+        let semaphore = Semaphore::new(usize::MAX);
+        semaphore.set_limit(0);
+        {
+            let _permit = semaphore.acquire();
+            assert_eq!(semaphore.permits.lock().unwrap().used, 1);
+        }
+        assert_eq!(semaphore.permits.lock().unwrap().used, 0);
+        // the single permit is available again
+        drop(semaphore.acquire());
+    }
+}
@@ -6,36 +6,57 @@
 //! - matched libraries will be taken out of the potential matches
 //!
 //!
+/// Aggregating the discovered matches into a single map.
+pub mod aggregate;
 /// The cli interface.
 mod cli;
 /// Query debian control files, and redirect pkg names.
 pub mod deb;
+/// Discovering many packages concurrently.
+pub mod discover;
+/// Translating runtime dependencies of binaries through their sonames.
+pub mod elf;
 /// Error handling.
 pub mod error;
+/// Structured records of failed discoveries.
+pub mod failures;
+/// Matching packages through the files they ship.
+pub mod files;
+/// The sources of the packages, that are discovered.
+pub mod input;
+/// The journal of a discovery sweep.
+pub mod journal;
+/// Limits of concurrent requests and subprocesses.
+pub mod limits;
 /// Matching package names.
 pub mod matcher;
 /// This module wraps the `nix` command.
 /// And provides convenience functions.
 pub mod nix;
+/// Coverage reports of discovery runs.
+pub mod report;
+/// Looking up the debian names of nix attributes.
+pub mod reverse;
 /// Setup helpers.
 pub mod setup;
 pub mod state;
+/// Fixtures of the unit tests.
+#[cfg(test)]
+mod testing;
 
-use chrono::Utc;
 use error::DebNixError;
 // use serde::{Deserialize, Serialize};
-use std::{
-    fs::{create_dir_all, File},
-    io::Write,
-    path::Path,
-};
 
 #[macro_use]
 extern crate log;
 
-use self::state::{create_output_map, State};
-use self::{cli::CliArgs, deb::read_popcon};
+use self::cli::CliArgs;
+use self::discover::{discover_all, Finished};
+use self::failures::ErrorSummary;
+use self::report::RunReport;
+use self::state::{create_output_map, to_canonical_json, State};
 use clap::Parser;
+use std::path::Path;
 
 fn main() -> Result<(), DebNixError> {
     env_logger::init();
@@ -48,73 +69,77 @@ fn main() -> Result<(), DebNixError> {
         std::process::exit(0);
     }
 
-    // Query a single debian pkg name.
-    if let Some(pkgs) = opts.pkg() {
+    // Query a single debian pkg name, a recursive query discovers its dependencies as well.
+    if let Some(pkgs) = opts.pkg().filter(|_| !state.recursive()) {
         state.discover_package(pkgs.clone())?;
     };
 
-    if let Some(location) = opts.generate_map() {
-        create_output_map(location)?;
+    // Translate the runtime dependencies of a binary.
+    if let Some(location) = opts.elf() {
+        state.discover_elf(location)?;
     }
 
-    if let Some(amount) = state.discover() {
-        let start_time = Utc::now().time();
-        let pop = read_popcon("./test/popcon.csv")?;
-        for (i, pkg) in pop
-            .into_iter()
-            .skip(state.discover_start().unwrap_or(0))
-            .enumerate()
-        {
-            if i == amount {
-                break;
-            }
-            if let Some(destination) = state.output() {
-                let target_destination = format!("{destination}/error");
-                create_dir_all(&target_destination)
-                    .map_err(|e| DebNixError::IoPath(format!("{e}: {target_destination}")))?;
-                let error_destination = format!("{}/error/{}", destination, pkg);
-                let destination = format!("{}/{}-debnix.json", destination, pkg);
-                // For now don't overwrite paths, but only create them once.
-                if !Path::new(&destination).exists() && !Path::new(&error_destination).exists() {
-                    match state.discover_pkg(pkg.clone()) {
-                        Ok(outputs) => {
-                            let serialized = serde_json::to_string(&outputs)?;
-                            let mut file = File::create(&destination)
-                                .map_err(|e| DebNixError::IoPath(format!("{e}: {destination}")))?;
-                            file.write_all(serialized.as_bytes())?;
-                            error!("Written to location: {}", &destination);
-                        }
-                        Err(e) => {
-                            error!("Discover Error: {}", e);
-                            if let Ok(mut file) = File::create(&error_destination) {
-                                file.write_all(e.to_string().as_bytes()).map_err(|e| {
-                                    DebNixError::IoPath(format!("{e}: {error_destination}"))
-                                })?;
-                                error!("Written to location: {}", &error_destination)
-                            } else {
-                                error!("Could not write to error location: {}", &error_destination);
-                            }
-                        }
-                    }
-                } else {
-                    if Path::new(&destination).exists() {
-                        error!("Path already exists: {}", &destination);
-                    }
-                    if Path::new(&error_destination).exists() {
-                        error!("Path already exists: {}", &error_destination);
-                    }
-                }
-            }
-            if let Some(duration) = state.timeout() {
-                let since_startup = Utc::now()
-                    .time()
-                    .signed_duration_since(start_time)
-                    .num_minutes();
+    // Look up the debian names of a nix attribute.
+    if let Some(attr_path) = opts.nix_attr() {
+        state.lookup_nix_attr(attr_path)?;
+    }
+
+    // Aggregate the failures of a discovery run.
+    if let Some(location) = opts.error_summary() {
+        let summary = ErrorSummary::from_output(Path::new(location))?;
+        print!("{}", to_canonical_json(&summary)?);
+    }
+
+    // Report the coverage of a discovery run.
+    if let Some(location) = opts.report() {
+        let report = RunReport::from_output(Path::new(location), opts.min_confidence())?;
+        print!("{}", report.render(opts.report_format())?);
+    }
 
-                if since_startup as usize > duration {
+    if let Some(location) = opts.generate_map() {
+        create_output_map(
+            &opts.map_inputs(),
+            Path::new(location),
+            opts.min_confidence(),
+            opts.conflict_policy(),
+            state.map(),
+        )?;
+    }
+
+    let recursive_pkg = opts.pkg().filter(|_| state.recursive());
+    if state.discover().is_some()
+        || opts.discover_from().is_some()
+        || recursive_pkg.is_some()
+        || state.resume()
+        || state.refresh()
+    {
+        let destination = state.output().ok_or_else(|| {
+            DebNixError::Config("A discovery needs an output directory (`--output`).".into())
+        })?;
+        // Without an explicit input, resuming and refreshing only work on the output directory.
+        let pkgs = if let Some(pkg) = recursive_pkg {
+            vec![pkg.clone()]
+        } else if state.discover().is_some() || opts.discover_from().is_some() {
+            opts.discover_from()
+                .cloned()
+                .unwrap_or_default()
+                .read(state.pipeline().infrastructure())?
+                .into_iter()
+                .skip(state.discover_start().unwrap_or(0))
+                .take(state.discover().unwrap_or(usize::MAX))
+                .collect::<Vec<String>>()
+        } else {
+            vec![]
+        };
+        match discover_all(&state, &pkgs, destination)? {
+            Finished::Completed => {}
+            finished => {
+                if finished == Finished::TimedOut {
                     println!("Timeout Exceeded, shutting down.");
-                    std::process::exit(0);
+                } else {
+                    println!("Interrupted, shutting down.");
                 }
+                std::process::exit(finished.exit_code());
             }
         }
     }
@@ -1,156 +1,792 @@
-use std::collections::HashMap;
+/// Ranking candidates for unmatched pkgs.
+pub mod fuzzy;
+/// The configurable matching pipeline.
+pub mod pipeline;
 
-use crate::nix::NIX_ATTRIBUTES_REVERSED;
-use crate::{deb::debian_redirect, error::DebNixError};
+use std::collections::{BTreeMap, HashMap};
+
+use control_file::map::MapEntry;
+pub use control_file::map::NixTarget;
+use serde::{Deserialize, Serialize};
+
+use self::fuzzy::{rank, RankedCandidate};
+use self::pipeline::{lowercase_index, Candidates, Pipeline};
+use crate::deb::DebRole;
+use crate::nix::{
+    NixInput, NIX_ATTRIBUTES_NEW, NIX_ATTRIBUTES_REVERSED, NIX_PACKAGE_SET_ATTRIBUTES,
+};
+use crate::{deb::debian_redirect_with_retry, error::DebNixError};
+
+/// The built-in rules, that can produce a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// The files the debian pkg ships are provided by the nix pkg.
+    Files,
+    /// `libxcb` -> `libxcb`
+    Exact,
+    /// `libxcb-dev` -> `libxcb`
+    StripDev,
+    /// `foo-utils` -> `foo`
+    StripRole,
+    /// `libX11-dev` -> `libx11`
+    CaseFold,
+    /// `gdk-pixbuf-dev` -> `gdk_pixbuf`
+    Underscore,
+    /// `libfoo2-dev` -> `foo`, strips `lib` and all digits, prone to false positives.
+    StripLibVersion,
+    /// `libfoo2-dev` -> `libfoo`, strips all digits.
+    StripVersion,
+    /// `lib-foo-bar-dev` -> `foobar`, strips `lib`, digits and dashes, prone to false positives.
+    StripLibDashes,
+    /// `python3-foo` -> `python3Packages.foo`
+    Ecosystem,
+}
+
+impl Strategy {
+    /// The name of the strategy, as used in the configuration.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Strategy::Files => "files",
+            Strategy::Exact => "exact",
+            Strategy::StripDev => "strip-dev",
+            Strategy::StripRole => "strip-role",
+            Strategy::CaseFold => "case-fold",
+            Strategy::Underscore => "underscore",
+            Strategy::StripLibVersion => "strip-lib-version",
+            Strategy::StripVersion => "strip-version",
+            Strategy::StripLibDashes => "strip-lib-dashes",
+            Strategy::Ecosystem => "ecosystem",
+        }
+    }
+
+    /// How much a match of this rule can be trusted, between `0.0` and `1.0`.
+    pub(crate) fn confidence(&self) -> f64 {
+        match self {
+            Strategy::Exact => 1.0,
+            Strategy::Files | Strategy::StripDev => 0.95,
+            Strategy::CaseFold => 0.9,
+            Strategy::StripRole | Strategy::Underscore => 0.85,
+            Strategy::Ecosystem => 0.8,
+            Strategy::StripVersion => 0.6,
+            Strategy::StripLibVersion => 0.5,
+            Strategy::StripLibDashes => 0.4,
+        }
+    }
+}
+
+/// The pass of the matcher, that produced a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pass {
+    /// The debian pkg name itself was matched.
+    Direct,
+    /// The name of the debian source pkg was matched.
+    Redirect,
+    /// The name of the debian source pkg was matched against all inputs,
+    /// including the ones that were already matched.
+    FullOutput,
+    /// The debian pkg name was matched against all of nixpkgs,
+    /// not only the inputs of the derivation.
+    Global,
+}
+
+impl Pass {
+    fn confidence(&self) -> f64 {
+        match self {
+            Pass::Direct => 1.0,
+            Pass::Redirect => 0.8,
+            Pass::FullOutput => 0.7,
+            Pass::Global => 0.5,
+        }
+    }
+}
+
+/// How a single debian pkg was matched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchInfo {
+    /// The name of the strategy, either a built-in [`Strategy`] or a configured rule.
+    strategy: String,
+    pass: Pass,
+    confidence: f64,
+    /// The concrete debian pkg, that was matched in place of a virtual one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+}
+
+impl MatchInfo {
+    pub(crate) fn new(strategy: &str, confidence: f64, pass: Pass) -> Self {
+        Self {
+            strategy: strategy.into(),
+            pass,
+            confidence: confidence * pass.confidence(),
+            provider: None,
+        }
+    }
+
+    /// Records, that the match went through a virtual pkg.
+    pub(crate) fn with_provider(self, provider: Option<String>) -> Self {
+        Self { provider, ..self }
+    }
+
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    pub fn strategy(&self) -> &str {
+        self.strategy.as_ref()
+    }
+}
+
+/// The most confident target of a debian pkg, for the flat format.
+/// Targets without a recorded confidence are assumed to be certain,
+/// ties are broken by the order of the targets.
+pub(crate) fn best_target(targets: &[NixTarget]) -> Option<&NixTarget> {
+    targets
+        .iter()
+        .fold(None, |best: Option<&NixTarget>, target| match best {
+            Some(best)
+                if best.confidence().unwrap_or(1.0) >= target.confidence().unwrap_or(1.0) =>
+            {
+                Some(best)
+            }
+            _ => Some(target),
+        })
+}
+
+/// Reads a map in either format, see [`MapEntry`].
+pub(crate) fn deserialize_targets<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Vec<NixTarget>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let map = BTreeMap::<String, MapEntry>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(deb, targets)| (deb, targets.into()))
+        .collect())
+}
+
+/// The result of matching: `{deb-lib: [nix-lib]}`,
+/// together with the information how each pkg was matched.
+/// Ordered by the debian pkgs, so that the generated outputs are deterministic.
+#[derive(Debug, Default)]
+pub(crate) struct Matches {
+    pub(crate) map: BTreeMap<String, Vec<NixTarget>>,
+    pub(crate) info: BTreeMap<String, MatchInfo>,
+    /// Ranked candidates for the pkgs, that couldn't be matched.
+    pub(crate) candidates: BTreeMap<String, Vec<RankedCandidate>>,
+    /// Debian infrastructure, that is not applicable to nix.
+    pub(crate) not_applicable: Vec<String>,
+    /// Virtual pkgs and the concrete pkgs, that they were resolved to: `{awk: mawk}`
+    pub(crate) virtuals: BTreeMap<String, String>,
+    /// The pkgs, that couldn't be redirected to their source pkg, with the reason.
+    /// They are skipped by the redirect passes, but not by the other passes.
+    pub(crate) redirect_failed: BTreeMap<String, String>,
+}
+
+impl Matches {
+    /// Records the targets of a match, which are either
+    /// a pname out of the candidates, or attribute paths.
+    fn insert(&mut self, lib: &str, targets: &[String], info: MatchInfo) {
+        let targets = targets
+            .iter()
+            .map(|target| NixTarget::new(target.clone(), None, Some(info.confidence())))
+            .collect();
+        self.map.insert(lib.to_owned(), targets);
+        self.info.insert(lib.to_owned(), info);
+    }
+}
 
 /// Matches the input pkgs with the output pkgs
 /// The input pkgs are assumed to come from debian and the output pkgs from nix
-/// Will use simple heuristics, in order to get a possible match.
+/// Will run the stages of the pipeline, in order to get a possible match.
+/// Virtual pkgs are matched through their concrete provider (`awk` -> `mawk`),
+/// if the pipeline has a `Packages` index.
+/// Matches target the output of the nix pkg that corresponds to the
+/// role of the debian pkg (`libxcb1-dev` -> `libxcb.dev`), if the output is used.
 pub(crate) fn match_libs(
     input: Vec<String>,
-    output: Vec<String>,
-) -> Result<HashMap<String, String>, DebNixError> {
-    let mut res_map = HashMap::new();
-    let mut input = input.to_vec();
+    nix_inputs: &[NixInput],
+    pipeline: &Pipeline,
+) -> Result<Matches, DebNixError> {
+    match_libs_with(input, nix_inputs, pipeline, &resolve_attr_path)
+}
+
+/// [`match_libs`], with the lookup of the attribute paths of the matched pnames.
+fn match_libs_with(
+    input: Vec<String>,
+    nix_inputs: &[NixInput],
+    pipeline: &Pipeline,
+    attr_path_of: &dyn Fn(&str) -> Option<String>,
+) -> Result<Matches, DebNixError> {
+    let mut res = Matches::default();
+    let (mut input, not_applicable) = pipeline.infrastructure().partition(input);
+    res.not_applicable = not_applicable;
+    // Only debian infrastructure is left, so there is nothing to map.
+    if input.is_empty() && !res.not_applicable.is_empty() {
+        return Ok(res);
+    }
+    if let Some(packages) = pipeline.packages() {
+        res.virtuals = input
+            .iter()
+            .filter_map(|lib| Some((lib.clone(), packages.resolve(lib)?.to_owned())))
+            .collect();
+    }
+    let virtuals = res.virtuals.clone();
+    let concrete = |lib: &String| virtuals.get(lib).unwrap_or(lib).clone();
+    let mut output = nix_inputs
+        .iter()
+        .map(|input| input.pname().to_owned())
+        .collect::<Vec<String>>();
+    output.dedup();
     let mut outputs = output.to_vec();
+    let mut nix_outputs: HashMap<&str, Vec<&str>> = HashMap::new();
+    for input in nix_inputs {
+        nix_outputs
+            .entry(input.pname())
+            .or_default()
+            .push(input.output());
+    }
 
-    if input.is_empty() || outputs.is_empty() {
+    // Without a derivation, only the global stage has something to match against.
+    if input.is_empty() || (outputs.is_empty() && !pipeline.is_global()) {
         return Err(DebNixError::NoMatches(format!(
             "Nothing to match, \ninput: \n{:?}, or \noutput: \n{:?} is empty!",
             &input, &output
         )));
     }
 
-    // manual matching of the inputs
-    input.retain(|lib| match match_inlib(lib, &mut outputs) {
-        (true, None) => true,
-        (true, Some(_)) => true,
-        (false, None) => false,
-        (false, Some(outlib)) => {
-            res_map.insert(lib.clone(), outlib.clone());
-            outputs.retain(|lib| *lib != outlib);
-            false
-        }
-    });
-    // redirect the remaining packages and match them afterwards
-    input.retain(|lib| {
-        let redirect = debian_redirect(lib).unwrap();
-        match match_inlib(&redirect, &mut outputs) {
-            (true, None) => true,
-            (true, Some(_)) => true,
-            (false, None) => false,
-            (false, Some(outlib)) => {
-                res_map.insert(lib.to_string(), outlib.clone());
-                outputs.retain(|lib| lib != &outlib);
-                false
-            }
-        }
-    });
-    // redirect the remaining packages and match them afterwards match remaining packages against
-    // the full output and don't take pkgs out of the outputs (multiple binaries in one pkg)
-    input.retain(|lib| {
-        let mut outputs = output.to_vec();
-        let redirect = debian_redirect(lib).unwrap();
-        match match_inlib(&redirect, &mut outputs) {
-            (true, None) => true,
-            (true, Some(_)) => true,
-            (false, None) => false,
-            (false, Some(outlib)) => {
-                res_map.insert(String::from(lib), outlib);
-                false
+    let mut redirects: HashMap<String, Option<String>> = HashMap::new();
+    for stage in pipeline.stages() {
+        input.retain(|lib| {
+            let name = match stage.pass() {
+                Pass::Direct | Pass::Global => concrete(lib),
+                // redirect the remaining packages and match them afterwards
+                Pass::Redirect | Pass::FullOutput => {
+                    let redirect = redirects.entry(lib.clone()).or_insert_with(|| {
+                        debian_redirect_with_retry(&concrete(lib))
+                            .map_err(|e| {
+                                warn!("Redirecting {}: {}", lib, e);
+                                res.redirect_failed.insert(lib.clone(), e.to_string());
+                            })
+                            .ok()
+                    });
+                    match redirect {
+                        Some(name) => name.clone(),
+                        None => return true,
+                    }
+                }
+            };
+            // match remaining packages against the full output and don't take
+            // pkgs out of the outputs (multiple binaries in one pkg)
+            let candidates = match stage.pass() {
+                Pass::FullOutput => Candidates::new(&output),
+                Pass::Direct | Pass::Redirect => Candidates::new(&outputs),
+                Pass::Global => {
+                    Candidates::new(&GLOBAL_CANDIDATES).with_lowercase(&GLOBAL_CANDIDATES_LOWERCASE)
+                }
+            };
+            let Some((strategy, matched)) = stage
+                .strategies()
+                .iter()
+                .find_map(|strategy| Some((strategy, strategy.match_pkg(&name, candidates)?)))
+            else {
+                return true;
+            };
+            if matches!(stage.pass(), Pass::Direct | Pass::Redirect) {
+                outputs.retain(|outlib| !matched.consumes().contains(outlib));
             }
-        }
-    });
+            res.insert(
+                lib,
+                matched.targets(),
+                MatchInfo::new(strategy.name(), matched.confidence(), stage.pass())
+                    .with_provider(virtuals.get(lib).cloned()),
+            );
+            false
+        });
+    }
 
     debug!("\nInput {:?}\n", &input);
     debug!("Output {:?}\n", &outputs);
 
+    // rank candidates for the remaining packages, so they can be reviewed
+    for lib in &input {
+        let candidates = rank(&concrete(lib), &output, pipeline.fuzzy_candidates())
+            .into_iter()
+            .map(|candidate| {
+                let attr_path =
+                    attr_path_of(candidate.name()).unwrap_or_else(|| candidate.name().into());
+                candidate.with_name(attr_path)
+            })
+            .collect::<Vec<RankedCandidate>>();
+        if !candidates.is_empty() {
+            res.candidates.insert(lib.clone(), candidates);
+        }
+    }
+
     // Switching matched pnames from the nix matches to their corresponding attribute paths,
     // because that is how they are likely to be consumed.
-    for value in res_map.values_mut() {
-        if let Some(attr_path) = NIX_ATTRIBUTES_REVERSED.get(value) {
-            if let Some(attr_path) = &attr_path.attrpath {
-                value.clear();
-                value.push_str(&attr_path.clone())
-            }
+    for (lib, targets) in &mut res.map {
+        let role = DebRole::from_name(virtuals.get(lib).unwrap_or(lib));
+        for target in targets.iter_mut() {
+            *target = resolve_target(
+                target,
+                attr_path_of(target.attr()),
+                role,
+                nix_outputs.get(target.attr()),
+            );
         }
     }
-    Ok(res_map)
+    Ok(res)
 }
 
-fn match_inlib(inlib: &str, outlibs: &mut Vec<String>) -> (bool, Option<String>) {
-    use regex::Regex;
-    // for version numbers
-    let ve = Regex::new(r"\d(.\d*)*").unwrap();
+/// The target of a matched pname, with the output that corresponds to the role
+/// of the debian pkg. An output is only selected, if the attribute path of the
+/// pname is known, since `pname.dev` is not an attribute path.
+/// Targets, that are not inputs of the derivation, keep their default output.
+fn resolve_target(
+    target: &NixTarget,
+    attr_path: Option<String>,
+    role: DebRole,
+    used: Option<&Vec<&str>>,
+) -> NixTarget {
+    match (attr_path, used) {
+        (Some(attr), Some(used)) => NixTarget::new(
+            attr,
+            select_output(role, Some(used)).map(String::from),
+            target.confidence(),
+        ),
+        (Some(attr), None) => NixTarget::from_attr_path(&attr, target.confidence()),
+        (None, _) => NixTarget::from_attr_path(&target.attr_path(), target.confidence()),
+    }
+}
 
-    // exact match
-    for outlib in &mut *outlibs {
-        if inlib == *outlib {
-            debug!("{:?}", inlib);
-            return (false, Some(outlib.to_string()));
+/// The attribute path of a pname, if it is known.
+fn resolve_attr_path(pname: &str) -> Option<String> {
+    NIX_ATTRIBUTES_REVERSED
+        .get(pname)
+        .and_then(|attributes| attributes.attrpath.clone())
+}
+
+/// Selects the output of a matched nix pkg that corresponds to the role of the
+/// debian pkg, out of the outputs that are used as inputs.
+/// The default output doesn't need to be selected explicitly.
+fn select_output<'a>(role: DebRole, used: Option<&Vec<&'a str>>) -> Option<&'a str> {
+    let used = used?;
+    role.nix_outputs()
+        .iter()
+        .find_map(|output| used.iter().find(|used| *used == output))
+        .copied()
+        .filter(|output| *output != "out")
+}
+
+lazy_static::lazy_static! {
+    /// Every pname and toplevel attribute name of nixpkgs,
+    /// the candidates of the global pass.
+    static ref GLOBAL_CANDIDATES: Vec<String> = {
+        let mut candidates = NIX_ATTRIBUTES_REVERSED
+            .keys()
+            .chain(NIX_ATTRIBUTES_NEW.keys())
+            .cloned()
+            .collect::<Vec<String>>();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    };
+    /// The global candidates by their lowercase names, so that the case insensitive
+    /// rules don't have to lowercase all of nixpkgs for every debian pkg.
+    static ref GLOBAL_CANDIDATES_LOWERCASE: HashMap<String, String> =
+        lowercase_index(&GLOBAL_CANDIDATES);
+    /// Normalized attribute names of the nested package sets, and the toplevel
+    /// (keyed by the empty set name) pointing to their actual attribute names.
+    static ref PACKAGE_SET_INDEX: HashMap<String, HashMap<String, String>> = {
+        let mut result = HashMap::new();
+        for (set, attributes) in NIX_PACKAGE_SET_ATTRIBUTES.iter() {
+            result.insert(set.clone(), normalized_index(attributes.keys()));
         }
+        result.insert(String::new(), normalized_index(NIX_ATTRIBUTES_NEW.keys()));
+        result
+    };
+}
+
+/// Index attribute names by their normalized form,
+/// on collisions the lexicographically smallest attribute wins.
+fn normalized_index<'a>(attrs: impl Iterator<Item = &'a String>) -> HashMap<String, String> {
+    let mut index: HashMap<String, String> = HashMap::new();
+    for attr in attrs {
+        index
+            .entry(normalize(attr))
+            .and_modify(|existing| {
+                if attr < existing {
+                    existing.clone_from(attr);
+                }
+            })
+            .or_insert_with(|| attr.clone());
     }
-    // replace `-dev`
-    for outlib in &mut *outlibs {
-        if inlib.replace("-dev", "") == *outlib {
-            debug!("{:?}", inlib);
-            return (false, Some(outlib.to_string()));
+    index
+}
+
+/// Normalizes a package name, so that different naming conventions
+/// of the same package compare equal: `Test-Simple`, `test_simple`, `test.simple`.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '-' | '_' | '.'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Language ecosystems, whose packages live inside of a nested package set
+/// in nixpkgs and follow a distinct naming convention in debian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Ecosystem {
+    /// `python3-setuptools` -> `python3Packages.setuptools`
+    Python,
+    /// `libtest-simple-perl` -> `perlPackages.TestSimple`
+    Perl,
+    /// `ruby-rspec-core` -> `rubyPackages.rspec-core`
+    Ruby,
+    /// `libghc-text-dev` -> `haskellPackages.text`
+    Haskell,
+    /// `libppx-deriving-ocaml-dev` -> `ocamlPackages.ppx_deriving`
+    OCaml,
+    /// `lua-lpeg` -> `luaPackages.lpeg`
+    Lua,
+    /// `r-cran-data.table` -> `rPackages.data_table`
+    R,
+    /// `node-semver` -> `nodePackages.semver`
+    Node,
+    /// `golang-github-spf13-cobra-dev` -> `cobra`,
+    /// go libraries don't have a package set, so they are looked up in the toplevel.
+    Go,
+}
+
+impl Ecosystem {
+    const ALL: [Ecosystem; 9] = [
+        Ecosystem::Python,
+        Ecosystem::Perl,
+        Ecosystem::Ruby,
+        Ecosystem::Haskell,
+        Ecosystem::OCaml,
+        Ecosystem::Lua,
+        Ecosystem::R,
+        Ecosystem::Node,
+        Ecosystem::Go,
+    ];
+
+    /// The nested package set, empty for the toplevel.
+    pub(crate) fn package_set(&self) -> &'static str {
+        match self {
+            Ecosystem::Python => "python3Packages",
+            Ecosystem::Perl => "perlPackages",
+            Ecosystem::Ruby => "rubyPackages",
+            Ecosystem::Haskell => "haskellPackages",
+            Ecosystem::OCaml => "ocamlPackages",
+            Ecosystem::Lua => "luaPackages",
+            Ecosystem::R => "rPackages",
+            Ecosystem::Node => "nodePackages",
+            Ecosystem::Go => "",
         }
     }
-    // replace `-dev` && lowercase
-    for outlib in &mut *outlibs {
-        if inlib.replace("-dev", "").to_lowercase() == *outlib.to_lowercase() {
-            debug!("{:?}", inlib);
-            return (false, Some(outlib.to_string()));
+
+    /// The confidence of a match in the ecosystem.
+    /// Go libraries are looked up by their repository name in the toplevel,
+    /// which is ambiguous for common names (`cobra`, `errors`, `log`).
+    pub(crate) fn confidence(&self) -> f64 {
+        match self {
+            Ecosystem::Go => 0.4,
+            _ => Strategy::Ecosystem.confidence(),
         }
     }
-    // replace `-dev` && lowercase && replace - _
-    for outlib in &mut *outlibs {
-        if inlib.replace("-dev", "").replace('-', "_").to_lowercase() == *outlib.to_lowercase() {
-            debug!("{:?}", inlib);
-            return (false, Some(outlib.to_string()));
+
+    /// Translates a debian package name into candidate names inside of the
+    /// package set, ordered by how likely they are.
+    /// Returns an empty Vec, if the package doesn't belong to the ecosystem.
+    pub(crate) fn candidates(&self, deb: &str) -> Vec<String> {
+        let strip = |prefixes: &[&str], suffixes: &[&str]| -> Option<String> {
+            let name = prefixes
+                .iter()
+                .find_map(|prefix| deb.strip_prefix(prefix))
+                .or(if prefixes.is_empty() { Some(deb) } else { None })?;
+            let name = suffixes
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix))
+                .or(if suffixes.is_empty() {
+                    Some(name)
+                } else {
+                    None
+                })?;
+            (!name.is_empty()).then(|| name.to_owned())
+        };
+        match self {
+            Ecosystem::Python => strip(&["python3-", "python-", "pypy3-"], &[])
+                .into_iter()
+                .collect(),
+            Ecosystem::Perl => strip(&["lib"], &["-perl"]).into_iter().collect(),
+            Ecosystem::Ruby => strip(&["ruby-"], &[]).into_iter().collect(),
+            Ecosystem::Haskell => strip(&["libghc-"], &["-dev", "-prof", "-doc"])
+                .into_iter()
+                .collect(),
+            Ecosystem::OCaml => strip(&[], &["-ocaml-dev", "-ocaml"])
+                .map(|name| name.strip_prefix("lib").map_or(name.clone(), String::from))
+                .into_iter()
+                .collect(),
+            Ecosystem::Lua => strip(&["lua-"], &[]).into_iter().collect(),
+            Ecosystem::R => strip(&["r-cran-", "r-bioc-", "r-other-"], &[])
+                .into_iter()
+                .collect(),
+            Ecosystem::Node => strip(&["node-"], &[]).into_iter().collect(),
+            Ecosystem::Go => {
+                // golang-<host>-<owner>-<repo>-dev
+                let Some(name) = strip(&["golang-"], &["-dev"]) else {
+                    return vec![];
+                };
+                let parts = name.split('-').collect::<Vec<&str>>();
+                if parts.len() < 3 {
+                    return vec![parts.join("-")];
+                }
+                vec![parts[2..].join("-"), parts[1..].join("-")]
+            }
         }
     }
-    // replace `-dev` && lowercase && replace - _ && replace lib
-    for outlib in &mut *outlibs {
-        if ve.replace_all(
-            &inlib
-                .replace("-dev", "")
-                .replace('-', "_")
-                .replace("lib", "")
-                .to_lowercase(),
-            "",
-        ) == *outlib.to_lowercase()
-        {
-            debug!("{:?}", inlib);
-            return (false, Some(outlib.to_string()));
+}
+
+/// Matches a debian package against the nested package set of its ecosystem.
+/// Returns the ecosystem and the attribute path, together with the outputs that correspond to it.
+pub(crate) fn match_ecosystem(
+    inlib: &str,
+    outlibs: &[String],
+) -> Option<(Ecosystem, String, Vec<String>)> {
+    for ecosystem in Ecosystem::ALL {
+        for candidate in ecosystem.candidates(inlib) {
+            let normalized = normalize(&candidate);
+            let set = ecosystem.package_set();
+            let Some(attr) = PACKAGE_SET_INDEX
+                .get(set)
+                .and_then(|index| index.get(&normalized))
+            else {
+                continue;
+            };
+            debug!("{:?} matched in {:?}", inlib, ecosystem);
+            let attr_path = if set.is_empty() {
+                attr.clone()
+            } else {
+                format!("{set}.{attr}")
+            };
+            // The pnames of nested packages usually carry the
+            // interpreter prefix: `perl5.36.0-Test-Simple`.
+            let pname = NIX_PACKAGE_SET_ATTRIBUTES
+                .get(set)
+                .and_then(|attributes| attributes.get(attr))
+                .map(|attributes| normalize(attributes.pname()));
+            let matched = outlibs
+                .iter()
+                .filter(|outlib| {
+                    let outlib = normalize(outlib);
+                    outlib == normalized || Some(&outlib) == pname.as_ref()
+                })
+                .cloned()
+                .collect();
+            return Some((ecosystem, attr_path, matched));
         }
     }
-    // replace `-dev` && lowercase && replace - _ && don't replace lib
-    for outlib in &mut *outlibs {
-        if ve.replace_all(
-            &inlib.replace("-dev", "").replace('-', "_").to_lowercase(),
-            "",
-        ) == *outlib.to_lowercase()
-        {
-            debug!("{:?}", inlib);
-            return (false, Some(outlib.to_string()));
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pipeline::{MatchStrategy, StrategyMatch};
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn normalize_naming_conventions() {
+        assert_eq!(normalize("Test-Simple"), "testsimple");
+        assert_eq!(normalize("test_simple"), "testsimple");
+        assert_eq!(normalize("test.simple"), "testsimple");
+        assert_eq!(normalize("data.table"), normalize("data_table"));
+    }
+
+    #[test]
+    fn ecosystem_candidates() {
+        let cases: &[(Ecosystem, &str, &[&str])] = &[
+            (Ecosystem::Python, "python3-setuptools", &["setuptools"]),
+            (Ecosystem::Python, "pypy3-cffi", &["cffi"]),
+            (Ecosystem::Perl, "libtest-simple-perl", &["test-simple"]),
+            (Ecosystem::Ruby, "ruby-rspec-core", &["rspec-core"]),
+            (Ecosystem::Haskell, "libghc-text-dev", &["text"]),
+            (
+                Ecosystem::OCaml,
+                "libppx-deriving-ocaml-dev",
+                &["ppx-deriving"],
+            ),
+            (Ecosystem::Lua, "lua-lpeg", &["lpeg"]),
+            (Ecosystem::R, "r-cran-data.table", &["data.table"]),
+            (Ecosystem::Node, "node-semver", &["semver"]),
+            (
+                Ecosystem::Go,
+                "golang-github-spf13-cobra-dev",
+                &["cobra", "spf13-cobra"],
+            ),
+            (Ecosystem::Go, "golang-go-dev", &["go"]),
+        ];
+        for (ecosystem, deb, expected) in cases {
+            assert_eq!(ecosystem.candidates(deb), *expected, "{deb}");
         }
     }
-    // replace `-dev` && lowercase && replace - "" && don't replace lib
-    for outlib in outlibs {
-        if ve.replace_all(
-            &inlib
-                .replace("-dev", "")
-                .replace('-', "")
-                .replace("lib", "")
-                .to_lowercase(),
-            "",
-        ) == *outlib.to_lowercase()
-        {
-            debug!("{:?}", inlib);
-            return (false, Some(outlib.to_string()));
+
+    #[test]
+    fn ecosystem_candidates_of_other_ecosystems() {
+        assert!(Ecosystem::Python.candidates("libxcb1").is_empty());
+        assert!(Ecosystem::Perl.candidates("libxcb1").is_empty());
+        assert!(Ecosystem::Go.candidates("golang-github-foo").is_empty());
+        // the prefix alone doesn't name a package
+        assert!(Ecosystem::Node.candidates("node-").is_empty());
+    }
+
+    #[test]
+    fn select_output_by_role() {
+        let used = vec!["out", "dev", "lib"];
+        assert_eq!(select_output(DebRole::Dev, Some(&used)), Some("dev"));
+        assert_eq!(select_output(DebRole::Lib, Some(&used)), Some("lib"));
+        // the default output is left implicit
+        assert_eq!(select_output(DebRole::Bin, Some(&used)), None);
+        assert_eq!(select_output(DebRole::Doc, Some(&used)), None);
+        assert_eq!(select_output(DebRole::Dev, Some(&vec!["out"])), None);
+        assert_eq!(select_output(DebRole::Dev, None), None);
+    }
+
+    #[test]
+    fn resolve_target_selects_outputs_of_known_attributes() {
+        let target = NixTarget::from_attr_path("libxcb", Some(0.9));
+        let used = vec!["out", "dev"];
+        let resolved = resolve_target(
+            &target,
+            Some("xorg.libxcb".into()),
+            DebRole::Dev,
+            Some(&used),
+        );
+        assert_eq!(resolved.attr_path(), "xorg.libxcb.dev");
+        assert_eq!(resolved.confidence(), Some(0.9));
+    }
+
+    #[test]
+    fn resolve_target_without_attribute_path() {
+        let target = NixTarget::from_attr_path("libfoo", None);
+        let used = vec!["out", "dev"];
+        let resolved = resolve_target(&target, None, DebRole::Dev, Some(&used));
+        assert_eq!(resolved.attr_path(), "libfoo");
+    }
+
+    #[test]
+    fn resolve_target_without_used_outputs() {
+        // matched through the global stage, or a configured rule
+        let target = NixTarget::from_attr_path("libxcb", Some(0.5));
+        let resolved = resolve_target(&target, Some("xorg.libxcb".into()), DebRole::Dev, None);
+        assert_eq!(resolved.attr_path(), "xorg.libxcb");
+        assert_eq!(resolved.confidence(), Some(0.5));
+        // attribute paths of direct rules are kept as they are
+        let target = NixTarget::from_attr_path("qt5.qtbase.dev", None);
+        let resolved = resolve_target(&target, None, DebRole::Dev, None);
+        assert_eq!(resolved.attr_path(), "qt5.qtbase.dev");
+    }
+
+    #[test]
+    fn go_matches_are_less_confident() {
+        assert!(Ecosystem::Go.confidence() < Ecosystem::Python.confidence());
+        assert_eq!(
+            Ecosystem::Python.confidence(),
+            Strategy::Ecosystem.confidence()
+        );
+    }
+
+    #[test]
+    fn only_infrastructure_is_not_applicable() {
+        let input = ["debhelper-compat", "dh-python"].map(String::from).to_vec();
+        let matches = match_libs(input.clone(), &[], &Pipeline::default()).unwrap();
+        assert!(matches.map.is_empty());
+        assert_eq!(matches.not_applicable, input);
+        assert!(matches!(
+            match_libs(vec![], &[], &Pipeline::default()),
+            Err(DebNixError::NoMatches(_))
+        ));
+    }
+
+    /// Matches debian pkgs to fixed candidates, while they are available.
+    struct Stub {
+        name: &'static str,
+        confidence: f64,
+        matches: &'static [(&'static str, &'static str)],
+    }
+
+    impl MatchStrategy for Stub {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn match_pkg(&self, lib: &str, candidates: Candidates) -> Option<StrategyMatch> {
+            let (_, candidate) = self.matches.iter().find(|(deb, _)| *deb == lib)?;
+            candidates
+                .names()
+                .iter()
+                .any(|name| name == candidate)
+                .then(|| StrategyMatch::candidate(candidate, self.confidence))
         }
     }
-    (true, None)
+
+    #[test]
+    fn match_libs_runs_the_stages_in_order() {
+        let first = Stub {
+            name: "first",
+            confidence: 0.7,
+            matches: &[("libfoo-dev", "foo"), ("libfoo2-dev", "foo")],
+        };
+        let second = Stub {
+            name: "second",
+            confidence: 0.8,
+            matches: &[("libfoo-dev", "bar"), ("libbar", "bar")],
+        };
+        let pipeline = Pipeline::from_stages(vec![
+            (
+                Pass::Direct,
+                vec![Arc::new(first) as Arc<dyn MatchStrategy>],
+            ),
+            (
+                Pass::Direct,
+                vec![Arc::new(second) as Arc<dyn MatchStrategy>],
+            ),
+        ]);
+        let nix_inputs: Vec<NixInput> = serde_json::from_str(
+            r#"[
+                { "pname": "foo", "output": "out" },
+                { "pname": "foo", "output": "dev" },
+                { "pname": "bar", "output": "out" },
+                { "pname": "baz", "output": "out" }
+            ]"#,
+        )
+        .unwrap();
+        let input = ["libfoo-dev", "libfoo2-dev", "libbar", "libbaz-dev"]
+            .map(String::from)
+            .to_vec();
+        let attr_paths = |pname: &str| match pname {
+            "foo" => Some(String::from("foo")),
+            "bar" => Some(String::from("xorg.bar")),
+            _ => None,
+        };
+        let matches = match_libs_with(input, &nix_inputs, &pipeline, &attr_paths).unwrap();
+
+        // the earlier stage wins, and the role selects the used output
+        assert_eq!(matches.map["libfoo-dev"][0].attr_path(), "foo.dev");
+        assert_eq!(matches.map["libfoo-dev"][0].confidence(), Some(0.7));
+        assert_eq!(matches.info["libfoo-dev"].strategy(), "first");
+        assert_eq!(matches.info["libfoo-dev"].pass, Pass::Direct);
+        // `foo` is consumed by `libfoo-dev`
+        assert!(!matches.map.contains_key("libfoo2-dev"));
+        assert_eq!(matches.map["libbar"][0].attr_path(), "xorg.bar");
+        assert_eq!(matches.info["libbar"].strategy(), "second");
+        assert_eq!(matches.info["libbar"].confidence(), 0.8);
+        // unmatched pkgs are ranked against the full output
+        assert_eq!(matches.candidates["libfoo2-dev"][0].name(), "foo");
+        assert_eq!(matches.candidates["libbaz-dev"][0].name(), "baz");
+    }
 }
@@ -0,0 +1,210 @@
+//! Similarity based ranking of candidates, for debian pkgs that no rule matched.
+use std::collections::HashSet;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::deb::DebRole;
+
+/// Candidates below this score are not worth reviewing.
+const MIN_SCORE: f64 = 0.3;
+
+/// A possible match for an unmatched debian pkg.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RankedCandidate {
+    name: String,
+    /// The similarity between `0.0` and `1.0`.
+    score: f64,
+}
+
+impl RankedCandidate {
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+
+    pub(crate) fn with_name(self, name: String) -> Self {
+        Self { name, ..self }
+    }
+}
+
+/// Strips the parts of a name, that carry no information about its identity:
+/// `libgtk-3-dev` -> `gtk`, `python3-Pillow` -> `python-pillow`
+fn clean(name: &str) -> String {
+    split_version(name).0
+}
+
+/// The version, that [`clean`] strips off of a name:
+/// `libgtk-3-dev` -> `3`, `libssl1.1` -> `1.1`
+fn version(name: &str) -> String {
+    split_version(name).1
+}
+
+fn split_version(name: &str) -> (String, String) {
+    lazy_static::lazy_static! {
+        static ref VERSION: Regex = Regex::new(r"\d+[a-z]?$|\d+").unwrap();
+    }
+    let name = DebRole::strip_suffix(name).unwrap_or(name);
+    let name = name.strip_prefix("lib").unwrap_or(name);
+    let name = name.to_lowercase().replace(['_', '.'], "-");
+    let version = VERSION
+        .find_iter(&name)
+        .map(|version| version.as_str())
+        .collect::<Vec<&str>>()
+        .join(".");
+    let name = VERSION
+        .replace_all(&name, "")
+        .split('-')
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    (name, version)
+}
+
+/// The character trigrams of a name, padded at the boundaries.
+fn trigrams(name: &str) -> HashSet<[char; 3]> {
+    let chars = format!("  {name} ").chars().collect::<Vec<char>>();
+    chars.windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+}
+
+/// The similarity of two cleaned names, the better of their normalized
+/// edit distance and their trigram overlap (jaccard index).
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (clean(a), clean(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let edit = strsim::normalized_levenshtein(&a, &b);
+    let (a, b) = (trigrams(&a), trigrams(&b));
+    let union = a.union(&b).count();
+    let trigram = if union == 0 {
+        0.0
+    } else {
+        a.intersection(&b).count() as f64 / union as f64
+    };
+    edit.max(trigram)
+}
+
+/// Ranks the candidates by their similarity to the debian pkg,
+/// and returns the best `amount` of them.
+/// Candidates with the same score, that carry the version of the pkg, rank first.
+pub fn rank(lib: &str, candidates: &[String], amount: usize) -> Vec<RankedCandidate> {
+    let lib_version = version(lib);
+    let mut ranked = candidates
+        .iter()
+        .map(|candidate| {
+            let same_version = version(candidate) == lib_version;
+            let candidate = RankedCandidate {
+                score: similarity(lib, candidate),
+                name: candidate.clone(),
+            };
+            (candidate, same_version)
+        })
+        .filter(|(candidate, _)| candidate.score >= MIN_SCORE)
+        .collect::<Vec<(RankedCandidate, bool)>>();
+    ranked.sort_by(|(a, a_version), (b, b_version)| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b_version.cmp(a_version))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    let mut ranked = ranked
+        .into_iter()
+        .map(|(candidate, _)| candidate)
+        .collect::<Vec<RankedCandidate>>();
+    ranked.dedup_by(|a, b| a.name == b.name);
+    ranked.truncate(amount);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(ranked: &[RankedCandidate]) -> Vec<&str> {
+        ranked.iter().map(RankedCandidate::name).collect()
+    }
+
+    #[test]
+    fn clean_names() {
+        assert_eq!(clean("libgtk-3-dev"), "gtk");
+        assert_eq!(clean("python3-Pillow"), "python-pillow");
+        assert_eq!(clean("libxml2_utils"), "xml-utils");
+    }
+
+    #[test]
+    fn versions_of_names() {
+        assert_eq!(version("libgtk-3-dev"), "3");
+        assert_eq!(version("gtk3"), "3");
+        assert_eq!(version("libssl1.1"), "1.1");
+        assert_eq!(version("gtk"), "");
+    }
+
+    #[test]
+    fn padded_trigrams() {
+        let trigrams = trigrams("ab");
+        assert_eq!(trigrams.len(), 3);
+        assert!(trigrams.contains(&[' ', ' ', 'a']));
+        assert!(trigrams.contains(&[' ', 'a', 'b']));
+        assert!(trigrams.contains(&['a', 'b', ' ']));
+        // repeated trigrams are only counted once
+        assert_eq!(super::trigrams("aaaa").len(), 4);
+    }
+
+    #[test]
+    fn similarity_of_names() {
+        assert_eq!(similarity("libgtk-3-dev", "gtk3"), 1.0);
+        assert_eq!(similarity("libgtk-3-dev", "GTK"), 1.0);
+        assert!(similarity("libxml2-dev", "libxml") > similarity("libxml2-dev", "libxslt"));
+        assert!(similarity("zlib1g-dev", "qt5") < MIN_SCORE);
+        // names, that consist of nothing but noise
+        assert_eq!(similarity("lib-dev", "gtk"), 0.0);
+        assert_eq!(similarity("gtk", "123"), 0.0);
+    }
+
+    #[test]
+    fn similarity_is_symmetric() {
+        for (a, b) in [("libpng-dev", "png"), ("libssl-dev", "openssl")] {
+            assert_eq!(similarity(a, b), similarity(b, a));
+        }
+    }
+
+    #[test]
+    fn rank_orders_by_score() {
+        let candidates = ["openssl", "libressl", "ssl", "zlib"].map(String::from);
+        let ranked = rank("libssl-dev", &candidates, 5);
+        assert_eq!(ranked[0].name(), "ssl");
+        assert_eq!(ranked[0].score(), 1.0);
+        assert!(ranked
+            .windows(2)
+            .all(|pair| pair[0].score() >= pair[1].score()));
+        // below the cutoff
+        assert!(!names(&ranked).contains(&"zlib"));
+        assert!(ranked
+            .iter()
+            .all(|candidate| candidate.score() >= MIN_SCORE));
+    }
+
+    #[test]
+    fn rank_ties_are_ordered_by_version_and_name() {
+        let candidates = ["gtk3", "gtk", "gtk2", "gtk"].map(String::from);
+        let ranked = rank("libgtk-3-dev", &candidates, 5);
+        assert_eq!(names(&ranked), ["gtk3", "gtk", "gtk2"]);
+        let ranked = rank("libgtk-dev", &candidates, 5);
+        assert_eq!(names(&ranked), ["gtk", "gtk2", "gtk3"]);
+    }
+
+    #[test]
+    fn rank_keeps_the_best_amount() {
+        let candidates = ["gtk3", "gtk2", "gtkmm", "gtk4"].map(String::from);
+        assert_eq!(
+            names(&rank("libgtk-3-dev", &candidates, 2)),
+            ["gtk3", "gtk2"]
+        );
+        assert!(rank("libgtk-3-dev", &candidates, 0).is_empty());
+        assert!(rank("libgtk-3-dev", &[], 5).is_empty());
+    }
+}
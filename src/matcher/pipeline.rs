@@ -0,0 +1,798 @@
+//! The matching pipeline: an ordered list of stages, each of which tries a list
+//! of strategies on every debian pkg that is still unmatched.
+//!
+//! The pipeline can be configured through a json file:
+//!
+//! ```json
+//! {
+//!   "stages": [
+//!     { "pass": "direct", "strategies": ["qt5", "exact", "strip-dev"] },
+//!     { "pass": "redirect", "strategies": ["exact", "strip-dev"] }
+//!   ],
+//!   "disabled": ["strip-lib-dashes"],
+//!   "global": false,
+//!   "ignore": ["xvfb"],
+//!   "unignore": ["fakeroot"],
+//!   "rules": [
+//!     { "name": "qt5", "pattern": "^qt(\\w+)5-dev$", "replace": "qt5.qt$1", "direct": true }
+//!   ]
+//! }
+//! ```
+use std::{collections::HashMap, fs, sync::Arc};
+
+use control_file::infrastructure::Infrastructure;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::{match_ecosystem, Pass, Strategy};
+use crate::deb::packages::PackagesIndex;
+use crate::deb::DebRole;
+use crate::error::DebNixError;
+use crate::files::{pname_of, FileMatcher};
+
+/// A match, that a strategy found for a single debian pkg.
+#[derive(Debug, Clone)]
+pub struct StrategyMatch {
+    /// Either a pname out of the candidates, or attribute paths.
+    targets: Vec<String>,
+    /// The candidates, that are used up by the match.
+    consumes: Vec<String>,
+    confidence: f64,
+}
+
+impl StrategyMatch {
+    /// A match of one of the candidates, which is used up by the match.
+    pub fn candidate(candidate: &str, confidence: f64) -> Self {
+        Self {
+            targets: vec![candidate.into()],
+            consumes: vec![candidate.into()],
+            confidence,
+        }
+    }
+
+    /// A match of an attribute path, that doesn't need to be one of the candidates.
+    pub fn attr_path(attr_path: String, consumes: Vec<String>, confidence: f64) -> Self {
+        Self::attr_paths(vec![attr_path], consumes, confidence)
+    }
+
+    /// A match of several attribute paths, for debian pkgs that are split up in nixpkgs.
+    pub fn attr_paths(attr_paths: Vec<String>, consumes: Vec<String>, confidence: f64) -> Self {
+        Self {
+            targets: attr_paths,
+            consumes,
+            confidence,
+        }
+    }
+
+    pub fn targets(&self) -> &[String] {
+        self.targets.as_ref()
+    }
+
+    pub fn consumes(&self) -> &[String] {
+        self.consumes.as_ref()
+    }
+
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+}
+
+/// The candidates of a stage, large candidate lists that are matched
+/// many times come with an index of their lowercase names.
+#[derive(Debug, Clone, Copy)]
+pub struct Candidates<'a> {
+    names: &'a [String],
+    lowercase: Option<&'a HashMap<String, String>>,
+}
+
+impl<'a> Candidates<'a> {
+    pub fn new(names: &'a [String]) -> Self {
+        Self {
+            names,
+            lowercase: None,
+        }
+    }
+
+    /// Uses an index built through [`lowercase_index`] out of the same names.
+    pub fn with_lowercase(self, lowercase: &'a HashMap<String, String>) -> Self {
+        Self {
+            lowercase: Some(lowercase),
+            ..self
+        }
+    }
+
+    pub fn names(&self) -> &'a [String] {
+        self.names
+    }
+
+    /// The first candidate, that is `name` in lowercase.
+    pub fn find_lowercase(&self, name: &str) -> Option<&'a String> {
+        match self.lowercase {
+            Some(lowercase) => lowercase.get(name),
+            None => self
+                .names
+                .iter()
+                .find(|candidate| candidate.to_lowercase() == name),
+        }
+    }
+}
+
+/// Maps the lowercase names to the first of the names, that they were built from.
+pub fn lowercase_index(names: &[String]) -> HashMap<String, String> {
+    let mut index = HashMap::with_capacity(names.len());
+    for name in names {
+        index
+            .entry(name.to_lowercase())
+            .or_insert_with(|| name.clone());
+    }
+    index
+}
+
+/// A single rule, that tries to match a debian pkg.
+pub trait MatchStrategy: Send + Sync {
+    /// The name of the strategy, used for configuration and recorded with every match.
+    fn name(&self) -> &str;
+
+    /// Tries to match the debian pkg against the candidates,
+    /// which are the pnames of the inputs of the nix derivation.
+    fn match_pkg(&self, lib: &str, candidates: Candidates) -> Option<StrategyMatch>;
+}
+
+/// The name based heuristics, that are built into the matcher.
+struct NameRule(Strategy);
+
+impl NameRule {
+    /// The name transformation of the rule, and if the
+    /// candidates are compared in lowercase.
+    fn transform(&self, lib: &str) -> Option<(String, bool)> {
+        lazy_static::lazy_static! {
+            // for version numbers
+            static ref VERSION: Regex = Regex::new(r"\d(.\d*)*").unwrap();
+        }
+        let stripped = lib.replace("-dev", "");
+        match self.0 {
+            Strategy::Exact => Some((lib.into(), false)),
+            Strategy::StripDev => Some((stripped, false)),
+            Strategy::StripRole => DebRole::strip_suffix(lib).map(|lib| (lib.into(), false)),
+            Strategy::CaseFold => Some((stripped.to_lowercase(), true)),
+            Strategy::Underscore => Some((stripped.replace('-', "_").to_lowercase(), true)),
+            Strategy::StripLibVersion => Some((
+                VERSION
+                    .replace_all(
+                        &stripped.replace('-', "_").replace("lib", "").to_lowercase(),
+                        "",
+                    )
+                    .into_owned(),
+                true,
+            )),
+            Strategy::StripVersion => Some((
+                VERSION
+                    .replace_all(&stripped.replace('-', "_").to_lowercase(), "")
+                    .into_owned(),
+                true,
+            )),
+            Strategy::StripLibDashes => Some((
+                VERSION
+                    .replace_all(
+                        &stripped.replace('-', "").replace("lib", "").to_lowercase(),
+                        "",
+                    )
+                    .into_owned(),
+                true,
+            )),
+            Strategy::Files | Strategy::Ecosystem => None,
+        }
+    }
+}
+
+impl MatchStrategy for NameRule {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn match_pkg(&self, lib: &str, candidates: Candidates) -> Option<StrategyMatch> {
+        let (name, lowercase) = self.transform(lib)?;
+        let candidate = if lowercase {
+            candidates.find_lowercase(&name)
+        } else {
+            candidates
+                .names()
+                .iter()
+                .find(|candidate| **candidate == name)
+        };
+        candidate.map(|candidate| {
+            debug!("{:?}", lib);
+            StrategyMatch::candidate(candidate, self.0.confidence())
+        })
+    }
+}
+
+/// Matches ecosystem specific packages against their nested package sets.
+struct EcosystemRule;
+
+impl MatchStrategy for EcosystemRule {
+    fn name(&self) -> &str {
+        Strategy::Ecosystem.name()
+    }
+
+    fn match_pkg(&self, lib: &str, candidates: Candidates) -> Option<StrategyMatch> {
+        let (ecosystem, attr_path, consumes) = match_ecosystem(lib, candidates.names())?;
+        Some(StrategyMatch::attr_path(
+            attr_path,
+            consumes,
+            ecosystem.confidence(),
+        ))
+    }
+}
+
+/// Matches packages through the files they ship.
+struct FilesRule(Arc<FileMatcher>);
+
+impl MatchStrategy for FilesRule {
+    fn name(&self) -> &str {
+        Strategy::Files.name()
+    }
+
+    fn match_pkg(&self, lib: &str, candidates: Candidates) -> Option<StrategyMatch> {
+        let provider = self.0.match_pkg(lib)?;
+        let consumes = pname_of(provider.attr())
+            .and_then(|pname| {
+                candidates
+                    .names()
+                    .iter()
+                    .find(|candidate| *candidate == pname)
+            })
+            .cloned()
+            .into_iter()
+            .collect();
+        Some(StrategyMatch::attr_path(
+            provider.target(),
+            consumes,
+            Strategy::Files.confidence(),
+        ))
+    }
+}
+
+/// A project specific rule from the configuration, that rewrites the debian pkg name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegexRule {
+    name: String,
+    /// Matched against the debian pkg name.
+    pattern: String,
+    /// The replacement, capture groups can be referenced through `$1`.
+    replace: String,
+    /// The replacement is an attribute path, that doesn't need to be
+    /// one of the inputs of the nix derivation.
+    /// Several whitespace separated attribute paths map a debian pkg
+    /// to all of them: `qt5.qtbase.dev qt5.qttools.dev`
+    #[serde(default)]
+    direct: bool,
+    #[serde(default = "RegexRule::default_confidence")]
+    confidence: f64,
+}
+
+impl RegexRule {
+    fn default_confidence() -> f64 {
+        0.9
+    }
+}
+
+/// A compiled [`RegexRule`].
+struct RegexStrategy {
+    rule: RegexRule,
+    pattern: Regex,
+}
+
+impl MatchStrategy for RegexStrategy {
+    fn name(&self) -> &str {
+        self.rule.name.as_ref()
+    }
+
+    fn match_pkg(&self, lib: &str, candidates: Candidates) -> Option<StrategyMatch> {
+        if !self.pattern.is_match(lib) {
+            return None;
+        }
+        let name = self.pattern.replace(lib, self.rule.replace.as_str());
+        if self.rule.direct {
+            let attr_paths = name
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<String>>();
+            let consumes = candidates
+                .names()
+                .iter()
+                .filter(|candidate| attr_paths.contains(candidate))
+                .cloned()
+                .collect();
+            return Some(StrategyMatch::attr_paths(
+                attr_paths,
+                consumes,
+                self.rule.confidence,
+            ));
+        }
+        candidates
+            .names()
+            .iter()
+            .find(|candidate| **candidate == name)
+            .map(|candidate| StrategyMatch::candidate(candidate, self.rule.confidence))
+    }
+}
+
+/// A single stage of the pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageConfig {
+    pass: Pass,
+    strategies: Vec<String>,
+}
+
+/// The configuration of the pipeline, every field is optional.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    /// Replaces the default stages, if set.
+    #[serde(default)]
+    stages: Option<Vec<StageConfig>>,
+    /// Strategies, that are left out of every stage.
+    #[serde(default)]
+    disabled: Vec<String>,
+    /// Project specific rules, without explicit stages they are tried
+    /// before the built-in name rules.
+    #[serde(default)]
+    rules: Vec<RegexRule>,
+    /// The amount of ranked candidates, that are kept for unmatched pkgs.
+    #[serde(default)]
+    fuzzy_candidates: Option<usize>,
+    /// Match the remaining pkgs against all of nixpkgs,
+    /// appends a global stage to the default, or the configured stages.
+    #[serde(default)]
+    global: bool,
+    /// Additional debian infrastructure patterns, that are not applicable to nix.
+    #[serde(default)]
+    ignore: Vec<String>,
+    /// Patterns, that are removed from the curated debian infrastructure.
+    #[serde(default)]
+    unignore: Vec<String>,
+}
+
+impl PipelineConfig {
+    /// Reads the configuration from a json file.
+    pub fn open(location: &str) -> Result<Self, DebNixError> {
+        let contents = fs::read_to_string(location)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {location}")))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Disables additional strategies.
+    pub fn disable(&mut self, strategies: impl IntoIterator<Item = String>) {
+        self.disabled.extend(strategies);
+    }
+
+    /// Overrides the classification of debian infrastructure.
+    pub fn override_infrastructure(
+        &mut self,
+        ignore: impl IntoIterator<Item = String>,
+        unignore: impl IntoIterator<Item = String>,
+    ) {
+        self.ignore.extend(ignore);
+        self.unignore.extend(unignore);
+    }
+
+    /// Enables matching against all of nixpkgs.
+    pub fn set_global(&mut self, global: bool) {
+        self.global |= global;
+    }
+
+    /// Overrides the amount of ranked candidates for unmatched pkgs.
+    pub fn set_fuzzy_candidates(&mut self, amount: usize) {
+        self.fuzzy_candidates = Some(amount);
+    }
+}
+
+/// A stage of the pipeline.
+pub struct Stage {
+    pass: Pass,
+    strategies: Vec<Arc<dyn MatchStrategy>>,
+}
+
+impl Stage {
+    pub fn pass(&self) -> Pass {
+        self.pass
+    }
+
+    pub fn strategies(&self) -> &[Arc<dyn MatchStrategy>] {
+        self.strategies.as_ref()
+    }
+}
+
+/// The ordered stages, that the matcher runs.
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    fuzzy_candidates: usize,
+    infrastructure: Infrastructure,
+    packages: Option<Arc<PackagesIndex>>,
+}
+
+impl std::fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut list = f.debug_list();
+        for stage in &self.stages {
+            list.entry(&(
+                stage.pass,
+                stage
+                    .strategies
+                    .iter()
+                    .map(|strategy| strategy.name())
+                    .collect::<Vec<&str>>(),
+            ));
+        }
+        list.finish()
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new(&PipelineConfig::default(), None).expect("The default pipeline is valid.")
+    }
+}
+
+/// The name rules of the global stage, the rules that strip `lib`
+/// produce too many false positives against all of nixpkgs.
+const GLOBAL_RULES: &[Strategy] = &[
+    Strategy::Exact,
+    Strategy::StripDev,
+    Strategy::StripRole,
+    Strategy::CaseFold,
+    Strategy::Underscore,
+    Strategy::StripVersion,
+];
+
+/// The default amount of ranked candidates for unmatched pkgs.
+const FUZZY_CANDIDATES: usize = 5;
+
+/// The built-in name rules, in the order they are tried.
+const NAME_RULES: &[Strategy] = &[
+    Strategy::Exact,
+    Strategy::StripDev,
+    Strategy::StripRole,
+    Strategy::CaseFold,
+    Strategy::Underscore,
+    Strategy::StripLibVersion,
+    Strategy::StripVersion,
+    Strategy::StripLibDashes,
+];
+
+impl Pipeline {
+    /// Builds the pipeline out of its configuration,
+    /// the file matcher is used by the `files` strategy, if it is available.
+    pub fn new(
+        config: &PipelineConfig,
+        files: Option<Arc<FileMatcher>>,
+    ) -> Result<Self, DebNixError> {
+        let mut available: Vec<Arc<dyn MatchStrategy>> = vec![];
+        for rule in &config.rules {
+            if !(0.0..=1.0).contains(&rule.confidence) {
+                return Err(DebNixError::Config(format!(
+                    "Confidence of rule {} is not within 0.0 and 1.0: {}",
+                    rule.name, rule.confidence
+                )));
+            }
+            let pattern = Regex::new(&rule.pattern).map_err(|e| {
+                DebNixError::Config(format!("Invalid pattern in rule {}: {e}", rule.name))
+            })?;
+            available.push(Arc::new(RegexStrategy {
+                rule: rule.clone(),
+                pattern,
+            }));
+        }
+        if let Some(files) = &files {
+            available.push(Arc::new(FilesRule(files.clone())));
+        }
+        available.extend(
+            NAME_RULES
+                .iter()
+                .map(|rule| Arc::new(NameRule(*rule)) as Arc<dyn MatchStrategy>),
+        );
+        available.push(Arc::new(EcosystemRule));
+        let is_known = |name: &str| {
+            // The file matcher is optional.
+            name == Strategy::Files.name()
+                || available.iter().any(|strategy| strategy.name() == name)
+        };
+        if let Some(name) = config.disabled.iter().find(|name| !is_known(name)) {
+            return Err(DebNixError::Config(format!(
+                "Unknown disabled strategy: {name}"
+            )));
+        }
+
+        let names = |rules: &[Strategy]| {
+            rules
+                .iter()
+                .map(|rule| String::from(rule.name()))
+                .collect::<Vec<String>>()
+        };
+        let mut stages = config.stages.clone().unwrap_or_else(|| {
+            let mut direct = config
+                .rules
+                .iter()
+                .map(|rule| rule.name.clone())
+                .collect::<Vec<String>>();
+            direct.extend(names(NAME_RULES));
+            vec![
+                StageConfig {
+                    pass: Pass::Direct,
+                    strategies: names(&[Strategy::Files]),
+                },
+                StageConfig {
+                    pass: Pass::Direct,
+                    strategies: direct,
+                },
+                StageConfig {
+                    pass: Pass::Direct,
+                    strategies: names(&[Strategy::Ecosystem]),
+                },
+                StageConfig {
+                    pass: Pass::Redirect,
+                    strategies: names(NAME_RULES),
+                },
+                StageConfig {
+                    pass: Pass::FullOutput,
+                    strategies: names(NAME_RULES),
+                },
+            ]
+        });
+        // Explicit stages can already contain a global stage.
+        if config.global && !stages.iter().any(|stage| stage.pass == Pass::Global) {
+            stages.push(StageConfig {
+                pass: Pass::Global,
+                strategies: names(GLOBAL_RULES),
+            });
+        }
+
+        let mut result = vec![];
+        for stage in stages {
+            let mut strategies = vec![];
+            for name in &stage.strategies {
+                if config.disabled.contains(name) {
+                    continue;
+                }
+                match available.iter().find(|strategy| strategy.name() == name) {
+                    Some(strategy) => strategies.push(strategy.clone()),
+                    // The file matcher is optional.
+                    None if name == Strategy::Files.name() => {}
+                    None => {
+                        return Err(DebNixError::Config(format!("Unknown strategy: {name}")));
+                    }
+                }
+            }
+            if !strategies.is_empty() {
+                result.push(Stage {
+                    pass: stage.pass,
+                    strategies,
+                });
+            }
+        }
+        Ok(Self {
+            stages: result,
+            fuzzy_candidates: config.fuzzy_candidates.unwrap_or(FUZZY_CANDIDATES),
+            infrastructure: Infrastructure::with_overrides(
+                config.ignore.iter().cloned(),
+                &config.unignore,
+            ),
+            packages: None,
+        })
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        self.stages.as_ref()
+    }
+
+    /// Whether the pipeline matches against all of nixpkgs.
+    pub fn is_global(&self) -> bool {
+        self.stages.iter().any(|stage| stage.pass == Pass::Global)
+    }
+
+    pub fn fuzzy_candidates(&self) -> usize {
+        self.fuzzy_candidates
+    }
+
+    /// Resolves virtual debian pkgs through the `Provides` of the index, before they are matched.
+    pub fn with_packages(self, packages: Option<Arc<PackagesIndex>>) -> Self {
+        Self { packages, ..self }
+    }
+
+    /// The index, that virtual debian pkgs are resolved with.
+    pub fn packages(&self) -> Option<&PackagesIndex> {
+        self.packages.as_deref()
+    }
+
+    /// The classification of debian infrastructure, that is not matched.
+    pub fn infrastructure(&self) -> &Infrastructure {
+        &self.infrastructure
+    }
+}
+
+#[cfg(test)]
+impl Pipeline {
+    /// A pipeline out of explicit stages, so that tests can run stub strategies.
+    pub(crate) fn from_stages(stages: Vec<(Pass, Vec<Arc<dyn MatchStrategy>>)>) -> Self {
+        Self {
+            stages: stages
+                .into_iter()
+                .map(|(pass, strategies)| Stage { pass, strategies })
+                .collect(),
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(config: &str) -> Result<Pipeline, DebNixError> {
+        let config: PipelineConfig = serde_json::from_str(config).unwrap();
+        Pipeline::new(&config, None)
+    }
+
+    fn stages(pipeline: &Pipeline) -> Vec<(Pass, Vec<&str>)> {
+        pipeline
+            .stages()
+            .iter()
+            .map(|stage| {
+                (
+                    stage.pass(),
+                    stage
+                        .strategies()
+                        .iter()
+                        .map(|strategy| strategy.name())
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_stages_without_file_matcher() {
+        let pipeline = pipeline("{}").unwrap();
+        let passes = stages(&pipeline)
+            .into_iter()
+            .map(|(pass, _)| pass)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            passes,
+            [Pass::Direct, Pass::Direct, Pass::Redirect, Pass::FullOutput]
+        );
+        assert!(!pipeline.is_global());
+        assert_eq!(pipeline.fuzzy_candidates(), FUZZY_CANDIDATES);
+    }
+
+    #[test]
+    fn configured_stages() {
+        let pipeline = pipeline(
+            r#"{
+                "stages": [
+                    { "pass": "direct", "strategies": ["qt5", "exact", "files"] },
+                    { "pass": "redirect", "strategies": ["strip-dev"] }
+                ],
+                "rules": [{ "name": "qt5", "pattern": "^qt5$", "replace": "qt5.qtbase", "direct": true }],
+                "fuzzy_candidates": 2
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            stages(&pipeline),
+            [
+                (Pass::Direct, vec!["qt5", "exact"]),
+                (Pass::Redirect, vec!["strip-dev"])
+            ]
+        );
+        assert_eq!(pipeline.fuzzy_candidates(), 2);
+    }
+
+    #[test]
+    fn disabled_strategies_are_left_out() {
+        let pipeline = pipeline(
+            r#"{
+                "stages": [
+                    { "pass": "direct", "strategies": ["exact", "strip-dev"] },
+                    { "pass": "redirect", "strategies": ["strip-dev"] }
+                ],
+                "disabled": ["strip-dev", "files"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(stages(&pipeline), [(Pass::Direct, vec!["exact"])]);
+    }
+
+    #[test]
+    fn global_stage() {
+        let pipeline = pipeline(r#"{ "global": true, "disabled": ["strip-version"] }"#).unwrap();
+        assert!(pipeline.is_global());
+        let (pass, strategies) = stages(&pipeline).pop().unwrap();
+        assert_eq!(pass, Pass::Global);
+        assert!(!strategies.contains(&"strip-version"));
+    }
+
+    #[test]
+    fn global_stage_with_configured_stages() {
+        let mut config: PipelineConfig = serde_json::from_str(
+            r#"{ "stages": [{ "pass": "direct", "strategies": ["exact"] }] }"#,
+        )
+        .unwrap();
+        config.set_global(true);
+        let configured = Pipeline::new(&config, None).unwrap();
+        assert!(configured.is_global());
+        assert_eq!(stages(&configured)[0], (Pass::Direct, vec!["exact"]));
+        assert_eq!(stages(&configured).pop().unwrap().0, Pass::Global);
+        // a configured global stage is not duplicated
+        let pipeline = pipeline(
+            r#"{
+                "stages": [{ "pass": "global", "strategies": ["exact"] }],
+                "global": true
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(stages(&pipeline), [(Pass::Global, vec!["exact"])]);
+    }
+
+    #[test]
+    fn unknown_strategies_are_rejected() {
+        let error = pipeline(r#"{ "stages": [{ "pass": "direct", "strategies": ["exakt"] }] }"#)
+            .unwrap_err();
+        assert!(matches!(error, DebNixError::Config(_)));
+        let error = pipeline(r#"{ "disabled": ["strip-devs"] }"#).unwrap_err();
+        assert!(matches!(error, DebNixError::Config(message) if message.contains("strip-devs")));
+        let mut config = PipelineConfig::default();
+        config.disable(["qt5".to_owned()]);
+        assert!(Pipeline::new(&config, None).is_err());
+    }
+
+    #[test]
+    fn lowercase_index_matches_like_the_candidates() {
+        let names = ["libX11", "libx11", "SDL2", "gdk_pixbuf"].map(String::from);
+        let index = lowercase_index(&names);
+        let indexed = Candidates::new(&names).with_lowercase(&index);
+        let candidates = Candidates::new(&names);
+        for lib in ["libX11-dev", "libsdl2-dev", "gdk-pixbuf-dev", "libfoo-dev"] {
+            for rule in [
+                Strategy::CaseFold,
+                Strategy::Underscore,
+                Strategy::StripVersion,
+            ] {
+                assert_eq!(
+                    NameRule(rule)
+                        .match_pkg(lib, indexed)
+                        .map(|matched| matched.targets().to_vec()),
+                    NameRule(rule)
+                        .match_pkg(lib, candidates)
+                        .map(|matched| matched.targets().to_vec()),
+                );
+            }
+        }
+        assert_eq!(indexed.find_lowercase("libx11").unwrap(), "libX11");
+        assert_eq!(indexed.find_lowercase("sdl2").unwrap(), "SDL2");
+    }
+
+    #[test]
+    fn invalid_rule_patterns_are_rejected() {
+        let error =
+            pipeline(r#"{ "rules": [{ "name": "broken", "pattern": "(", "replace": "" }] }"#)
+                .unwrap_err();
+        assert!(matches!(error, DebNixError::Config(message) if message.contains("broken")));
+    }
+
+    #[test]
+    fn rule_confidences_are_bounded() {
+        let rule = |confidence: &str| {
+            pipeline(&format!(
+                r#"{{ "rules": [{{ "name": "qt5", "pattern": "^qt5$", "replace": "qt5.qtbase", "confidence": {confidence} }}] }}"#
+            ))
+        };
+        for confidence in ["0.0", "0.5", "1.0"] {
+            assert!(rule(confidence).is_ok());
+        }
+        for confidence in ["-0.1", "1.5", "90"] {
+            let error = rule(confidence).unwrap_err();
+            assert!(matches!(error, DebNixError::Config(message) if message.contains("qt5")));
+        }
+    }
+}
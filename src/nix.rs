@@ -1,6 +1,14 @@
+/// Reading nix-index databases.
+pub mod index;
+
 use crate::error::DebNixError;
+use crate::limits::NIX;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, process::Command};
+use std::{collections::HashMap, process::Command, sync::OnceLock};
+
+/// The channel of the default nix expression, that the toplevel attributes
+/// are exposed in, set once [`NIX_ATTRIBUTES_NEW`] is evaluated.
+static NIX_CHANNEL: OnceLock<String> = OnceLock::new();
 
 lazy_static::lazy_static! {
     /// Attribute names, that are exposed for the x86_64-linux
@@ -20,6 +28,10 @@ lazy_static::lazy_static! {
     let deserialized: HashMap<String, NixAttributes> = serde_json::from_str(serialized)
             .expect("Serializing from NIX_ATTRIBUTES broken.");
         // deserialized.iter_mut().map(|(mut k, _v)| k = &k.split_once('.').unwrap().1.to_owned()).collect::<Vec<_>>();
+        let channel = deserialized.keys().filter_map(|k| k.split_once('.')).map(|(channel, _)| channel).min();
+        if let Some(channel) = channel {
+            let _ = NIX_CHANNEL.set(channel.to_owned());
+        }
         deserialized.iter().map(|(k, v)| (k.split_once('.').unwrap().1.to_owned(), v.clone())).collect::<HashMap<String, NixAttributes>>()
     };
     pub static ref NIX_ATTRIBUTES: Vec<String> = {
@@ -37,6 +49,72 @@ lazy_static::lazy_static! {
         }
             result
     };
+    /// Attribute names of the nested package sets in [`NIX_PACKAGE_SETS`],
+    /// keyed by the package set they are exposed in.
+    /// Sets that fail to evaluate are left empty.
+    pub static ref NIX_PACKAGE_SET_ATTRIBUTES: HashMap<String, HashMap<String, NixAttributes>> = {
+        let mut result = HashMap::new();
+        for set in NIX_PACKAGE_SETS {
+            let attributes = query_package_set(set).unwrap_or_else(|e| {
+                error!("Could not query package set {}: {}", set, e);
+                HashMap::new()
+            });
+            result.insert(String::from(*set), attributes);
+        }
+        result
+    };
+}
+
+/// Nested package sets, that are not surfaced by a toplevel
+/// `nix-env --query --available`, but contain ecosystem specific packages.
+pub(crate) const NIX_PACKAGE_SETS: &[&str] = &[
+    "python3Packages",
+    "perlPackages",
+    "rubyPackages",
+    "haskellPackages",
+    "ocamlPackages",
+    "luaPackages",
+    "rPackages",
+    "nodePackages",
+];
+
+/// Queries the attributes of a single nested package set,
+/// the keys are the attribute names relative to the package set.
+/// The package set is read from the same channel as the toplevel attributes,
+/// without instantiating its derivations, since only the names are needed.
+pub(crate) fn query_package_set(set: &str) -> Result<HashMap<String, NixAttributes>, DebNixError> {
+    lazy_static::initialize(&NIX_ATTRIBUTES_NEW);
+    let channel = NIX_CHANNEL
+        .get()
+        .ok_or_else(|| DebNixError::Nix("No nix channel with toplevel attributes.".into()))?;
+    let _permit = NIX.acquire();
+    let output = Command::new("nix-env")
+        .arg("--query")
+        .arg("--json")
+        .arg("--attr-path")
+        .arg("--available")
+        .arg("--attr")
+        .arg(format!("{channel}.{set}"))
+        .output()?;
+
+    if !output.status.success() {
+        return Err(DebNixError::Nix(
+            std::str::from_utf8(&output.stderr)?.to_string(),
+        ));
+    }
+
+    let serialized = std::str::from_utf8(&output.stdout)?;
+    let deserialized: HashMap<String, NixAttributes> = serde_json::from_str(serialized)?;
+    let prefix = format!("{set}.");
+    Ok(deserialized
+        .into_iter()
+        .map(|(k, v)| {
+            let attr = k
+                .split_once(&prefix)
+                .map_or_else(|| k.clone(), |(_, attr)| attr.to_owned());
+            (attr, v)
+        })
+        .collect())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,17 +125,57 @@ pub struct NixAttributes {
     pub attrpath: Option<String>,
 }
 
+impl NixAttributes {
+    pub(crate) fn pname(&self) -> &str {
+        self.pname.as_ref()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Wraps the derivation output from `nix`,
 /// only few attributes are actually captured.
 pub(crate) struct SimpleDerivation {
     env: DerivationEnv,
+    #[serde(default)]
+    outputs: HashMap<String, DerivationOutput>,
 }
 
 impl SimpleDerivation {
     pub(crate) fn env(&self) -> &DerivationEnv {
         &self.env
     }
+
+    /// The name of the output, that is realised at the given store path.
+    pub(crate) fn output_of(&self, store_path: &str) -> Option<&String> {
+        self.outputs
+            .iter()
+            .find(|(_, output)| output.path.as_deref() == Some(store_path))
+            .map(|(name, _)| name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A single output (`out`, `dev`, `lib`, ...) of a derivation.
+pub(crate) struct DerivationOutput {
+    path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+/// An input of a derivation,
+/// together with the output of the input that is depended upon.
+pub(crate) struct NixInput {
+    pname: String,
+    output: String,
+}
+
+impl NixInput {
+    pub(crate) fn pname(&self) -> &str {
+        self.pname.as_ref()
+    }
+
+    pub(crate) fn output(&self) -> &str {
+        self.output.as_ref()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -97,8 +215,9 @@ impl DerivationEnv {
 }
 
 /// Wraps the nix command in order to surface information about derivations that
-/// make up a certain package.
-pub(crate) fn find_package_info(pkgs: &str) -> Result<SimpleDerivation, DebNixError> {
+/// make up a certain package, together with the path of the derivation.
+pub(crate) fn find_derivation(pkgs: &str) -> Result<(String, SimpleDerivation), DebNixError> {
+    let _permit = NIX.acquire();
     let output = if pkgs.starts_with('/') {
         Command::new("nix")
             .arg("show-derivation")
@@ -119,19 +238,28 @@ pub(crate) fn find_package_info(pkgs: &str) -> Result<SimpleDerivation, DebNixEr
 
     let serialized = std::str::from_utf8(&output.stdout)?;
     let deserialized: HashMap<String, SimpleDerivation> = serde_json::from_str(serialized)?;
-    let deserialized: SimpleDerivation = deserialized
-        .into_values()
-        .collect::<Vec<SimpleDerivation>>()
-        .first()
-        .unwrap()
-        .clone();
-    Ok(deserialized)
+    deserialized
+        .into_iter()
+        .next()
+        .ok_or_else(|| DebNixError::Nix(format!("No derivation for {pkgs}")))
+}
+
+/// Surfaces information about the derivation that makes up a certain package.
+pub(crate) fn find_package_info(pkgs: &str) -> Result<SimpleDerivation, DebNixError> {
+    Ok(find_derivation(pkgs)?.1)
+}
+
+/// The path of the derivation of a package, it changes with every change
+/// of the package, or of one of its dependencies in nixpkgs.
+pub(crate) fn get_drv_path(pkgs: &str) -> Result<String, DebNixError> {
+    Ok(find_derivation(pkgs)?.0)
 }
 
 /// Collects all the `pnames` of the `buildInputs` and `nativeBuildInputs`
-/// of a derivation into a Vec.
-pub(crate) fn get_drv_inputs(pkgs: &str) -> Result<Vec<String>, DebNixError> {
-    let derivation = find_package_info(pkgs)?;
+/// of a derivation into a Vec, together with the output that is used.
+/// The path of the derivation is returned as well.
+pub(crate) fn get_drv_inputs(pkgs: &str) -> Result<(String, Vec<NixInput>), DebNixError> {
+    let (drv_path, derivation) = find_derivation(pkgs)?;
     debug!("Nix derivation:\n {:?}", derivation);
     let mut inputs = vec![];
     let mut input_names = vec![];
@@ -161,11 +289,17 @@ pub(crate) fn get_drv_inputs(pkgs: &str) -> Result<Vec<String>, DebNixError> {
         let maybe_drv = find_package_info(drv);
         if let Ok(maybe_name) = maybe_drv {
             if let Some(name) = maybe_name.env().pname() {
-                input_names.push(name.clone());
+                let output = maybe_name
+                    .output_of(drv)
+                    .map_or_else(|| String::from("out"), String::clone);
+                input_names.push(NixInput {
+                    pname: name.clone(),
+                    output,
+                });
             }
         } else {
             error!("Error {:?}", &maybe_drv);
         }
     }
-    Ok(input_names)
+    Ok((drv_path, input_names))
 }
@@ -0,0 +1,545 @@
+//! Reader for the `files` database of [nix-index](https://github.com/nix-community/nix-index).
+//!
+//! The database starts with the magic `NIXI` and a little endian `u64` format version,
+//! followed by a zstd compressed stream of frcode encoded lines:
+//!
+//! ```text
+//! <metadata> <\x00> <shared prefix differential> <additional path bytes> <\n>
+//! ```
+//!
+//! The file entries of a store path come first, followed by an entry with the
+//! metadata `p`, whose path is the json encoded store path itself.
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DebNixError;
+use crate::files::{FileKey, NixFileIndex, NixFileProvider};
+
+const FILE_MAGIC: &[u8] = b"NIXI";
+const FORMAT_VERSION: u64 = 1;
+
+/// The metadata and the path of a decoded line.
+type Line = (Vec<u8>, Vec<u8>);
+
+/// The kind of a file inside of the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NixIndexFileKind {
+    Regular { size: u64, executable: bool },
+    Symlink { target: String },
+    Directory,
+}
+
+impl NixIndexFileKind {
+    fn decode(meta: &[u8]) -> Option<Self> {
+        let (kind, rest) = meta.split_last()?;
+        let rest = std::str::from_utf8(rest).ok()?;
+        match kind {
+            b'r' | b'x' => Some(NixIndexFileKind::Regular {
+                size: rest.parse().ok()?,
+                executable: *kind == b'x',
+            }),
+            b's' => Some(NixIndexFileKind::Symlink {
+                target: rest.into(),
+            }),
+            b'd' => Some(NixIndexFileKind::Directory),
+            _ => None,
+        }
+    }
+}
+
+/// The attribute, that a store path was discovered through.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NixIndexOrigin {
+    attr: String,
+    output: String,
+    /// Paths, that are only in the closure of an attribute are not toplevel.
+    toplevel: bool,
+    system: Option<String>,
+}
+
+/// A store path inside of the database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NixIndexStorePath {
+    store_dir: String,
+    hash: String,
+    name: String,
+    origin: NixIndexOrigin,
+}
+
+impl NixIndexStorePath {
+    pub fn attr(&self) -> &str {
+        self.origin.attr.as_ref()
+    }
+
+    pub fn output(&self) -> &str {
+        self.origin.output.as_ref()
+    }
+
+    pub fn toplevel(&self) -> bool {
+        self.origin.toplevel
+    }
+
+    /// The full path in the store: `/nix/store/<hash>-<name>`
+    pub fn path(&self) -> String {
+        format!("{}/{}-{}", self.store_dir, self.hash, self.name)
+    }
+}
+
+/// A single file of a store path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NixIndexFile {
+    /// The path relative to the store path: `/lib/pkgconfig/xcb.pc`
+    path: String,
+    kind: NixIndexFileKind,
+}
+
+impl NixIndexFile {
+    pub fn path(&self) -> &str {
+        self.path.as_ref()
+    }
+
+    pub fn kind(&self) -> &NixIndexFileKind {
+        &self.kind
+    }
+}
+
+/// A result of a query, a file together with the attribute output that provides it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NixIndexEntry {
+    attr: String,
+    output: String,
+    path: String,
+    toplevel: bool,
+}
+
+impl NixIndexEntry {
+    pub fn attr(&self) -> &str {
+        self.attr.as_ref()
+    }
+
+    pub fn output(&self) -> &str {
+        self.output.as_ref()
+    }
+
+    /// The path relative to the store path: `/lib/pkgconfig/xcb.pc`
+    pub fn path(&self) -> &str {
+        self.path.as_ref()
+    }
+
+    pub fn toplevel(&self) -> bool {
+        self.toplevel
+    }
+}
+
+/// Streams the store paths and their files out of a nix-index database.
+pub struct NixIndexReader<R: BufRead> {
+    reader: R,
+    last_path: Vec<u8>,
+    shared_len: usize,
+}
+
+impl NixIndexReader<BufReader<zstd::Decoder<'static, BufReader<File>>>> {
+    /// Opens a database, by default located at `~/.cache/nix-index/files`.
+    pub fn open(location: &Path) -> Result<Self, DebNixError> {
+        let file = File::open(location)
+            .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?;
+        Self::from_reader(file)
+    }
+}
+
+impl<R: Read> NixIndexReader<BufReader<zstd::Decoder<'static, BufReader<R>>>> {
+    /// Checks the header and starts decompressing the database.
+    pub fn from_reader(mut reader: R) -> Result<Self, DebNixError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != FILE_MAGIC {
+            return Err(DebNixError::NixIndex(format!(
+                "Not a nix-index database, found magic: {:?}",
+                magic
+            )));
+        }
+        let mut version = [0u8; 8];
+        reader.read_exact(&mut version)?;
+        let version = u64::from_le_bytes(version);
+        if version != FORMAT_VERSION {
+            return Err(DebNixError::NixIndex(format!(
+                "Unsupported nix-index database version: {version}, expected: {FORMAT_VERSION}"
+            )));
+        }
+        Ok(Self {
+            reader: BufReader::new(zstd::Decoder::new(reader)?),
+            last_path: vec![],
+            shared_len: 0,
+        })
+    }
+}
+
+impl<R: BufRead> NixIndexReader<R> {
+    /// Decodes the next line, returns `None` at the end of the database.
+    fn next_line(&mut self) -> Result<Option<Line>, DebNixError> {
+        let mut meta = vec![];
+        if self.reader.read_until(b'\0', &mut meta)? == 0 {
+            return Ok(None);
+        }
+        if meta.pop() != Some(b'\0') {
+            return Err(DebNixError::NixIndex("Missing NUL byte in entry.".into()));
+        }
+        let mut diff = [0u8; 1];
+        self.reader.read_exact(&mut diff)?;
+        let diff = if diff[0] == 0x80 {
+            let mut diff = [0u8; 2];
+            self.reader.read_exact(&mut diff)?;
+            i16::from_be_bytes(diff)
+        } else {
+            i16::from(diff[0] as i8)
+        };
+        let shared_len = self
+            .shared_len
+            .checked_add_signed(isize::from(diff))
+            .filter(|shared_len| *shared_len <= self.last_path.len())
+            .ok_or_else(|| {
+                DebNixError::NixIndex(format!(
+                    "Shared prefix out of range: {} + {diff}",
+                    self.shared_len
+                ))
+            })?;
+        let mut path = self.last_path[..shared_len].to_vec();
+        self.reader.read_until(b'\n', &mut path)?;
+        if path.pop() != Some(b'\n') {
+            return Err(DebNixError::NixIndex("Missing newline in entry.".into()));
+        }
+        self.shared_len = shared_len;
+        self.last_path.clone_from(&path);
+        Ok(Some((meta, path)))
+    }
+
+    /// Decodes the next store path, together with all of its files.
+    fn next_store_path(
+        &mut self,
+    ) -> Result<Option<(NixIndexStorePath, Vec<NixIndexFile>)>, DebNixError> {
+        let mut files = vec![];
+        while let Some((meta, path)) = self.next_line()? {
+            if meta == b"p" {
+                let store_path = serde_json::from_slice(&path)?;
+                return Ok(Some((store_path, files)));
+            }
+            let kind = NixIndexFileKind::decode(&meta).ok_or_else(|| {
+                DebNixError::NixIndex(format!(
+                    "Could not parse entry: {:?}",
+                    String::from_utf8_lossy(&meta)
+                ))
+            })?;
+            files.push(NixIndexFile {
+                path: String::from_utf8_lossy(&path).into_owned(),
+                kind,
+            });
+        }
+        if files.is_empty() {
+            Ok(None)
+        } else {
+            Err(DebNixError::NixIndex(
+                "Found file entries without a matching store path.".into(),
+            ))
+        }
+    }
+
+    /// Collects every file, whose path matches the predicate.
+    pub fn query(
+        self,
+        mut predicate: impl FnMut(&str) -> bool,
+    ) -> Result<Vec<NixIndexEntry>, DebNixError> {
+        let mut result = vec![];
+        for store_path in self {
+            let (store_path, files) = store_path?;
+            for file in files {
+                if predicate(&file.path) {
+                    result.push(NixIndexEntry {
+                        attr: store_path.attr().into(),
+                        output: store_path.output().into(),
+                        path: file.path,
+                        toplevel: store_path.toplevel(),
+                    });
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl<R: BufRead> Iterator for NixIndexReader<R> {
+    type Item = Result<(NixIndexStorePath, Vec<NixIndexFile>), DebNixError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_store_path().transpose()
+    }
+}
+
+/// The files of a nix-index database, that are relevant for file based matching.
+/// Only toplevel store paths are considered, since the attributes of paths
+/// in a closure don't provide the files themselves.
+#[derive(Debug, Default)]
+pub struct NixIndexFiles {
+    providers: HashMap<FileKey, Vec<NixFileProvider>>,
+}
+
+impl NixIndexFiles {
+    pub fn open(location: &Path) -> Result<Self, DebNixError> {
+        let mut providers: HashMap<FileKey, Vec<NixFileProvider>> = HashMap::new();
+        let entries =
+            NixIndexReader::open(location)?.query(|path| FileKey::from_nix_path(path).is_some())?;
+        for entry in entries.into_iter().filter(NixIndexEntry::toplevel) {
+            let Some(key) = FileKey::from_nix_path(entry.path()) else {
+                continue;
+            };
+            let provider = NixFileProvider::new(entry.attr, entry.output);
+            let entry = providers.entry(key).or_default();
+            if !entry.contains(&provider) {
+                entry.push(provider);
+            }
+        }
+        Ok(Self { providers })
+    }
+}
+
+impl NixFileIndex for NixIndexFiles {
+    fn providers(&self, key: &FileKey) -> Vec<NixFileProvider> {
+        self.providers.get(key).cloned().unwrap_or_default()
+    }
+}
+
+/// Checks if a file is a nix-index database.
+pub fn is_nix_index(location: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(location)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| magic == FILE_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Encodes the lines of a single store path,
+    /// nix-index starts a new encoder for every store path.
+    struct Encoder<'a> {
+        writer: &'a mut Vec<u8>,
+        last: Vec<u8>,
+        shared_len: i16,
+    }
+
+    impl<'a> Encoder<'a> {
+        fn new(writer: &'a mut Vec<u8>) -> Self {
+            Self {
+                writer,
+                last: vec![],
+                shared_len: 0,
+            }
+        }
+
+        fn write(&mut self, meta: &[u8], path: &[u8], shared: i16) {
+            let diff = shared - self.shared_len;
+            self.writer.extend(meta);
+            self.writer.push(b'\0');
+            if diff.abs() < i16::from(i8::MAX) {
+                self.writer.extend(diff.to_be_bytes().last());
+            } else {
+                self.writer.push(0x80);
+                self.writer.extend(diff.to_be_bytes());
+            }
+            self.writer.extend(&path[shared as usize..]);
+            self.writer.push(b'\n');
+            self.last = path.to_vec();
+            self.shared_len = shared;
+        }
+
+        fn file(&mut self, meta: &str, path: &str) {
+            let shared = self
+                .last
+                .iter()
+                .zip(path.as_bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            self.write(meta.as_bytes(), path.as_bytes(), shared as i16);
+        }
+
+        /// The store path entry doesn't share a prefix with the files.
+        fn footer(&mut self, store_path: &[u8]) {
+            self.write(b"p", store_path, 0);
+        }
+    }
+
+    fn store_path(attr: &str, output: &str, toplevel: bool) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "store_dir": "/nix/store",
+            "hash": "0m2mjrwm4hbq2pnyh9kw6wq1jv4bzzzm",
+            "name": format!("{attr}-1.0-{output}"),
+            "origin": {
+                "attr": attr,
+                "output": output,
+                "toplevel": toplevel,
+                "system": "x86_64-linux",
+            },
+        }))
+        .unwrap()
+    }
+
+    /// A small database with a multiple output library, a package with a deep
+    /// directory tree (exceeding a single byte prefix differential)
+    /// and a store path that is only part of a closure.
+    fn generate_database() -> Vec<u8> {
+        let mut lines = vec![];
+        let mut encoder = Encoder::new(&mut lines);
+        encoder.file("2d", "/lib");
+        encoder.file("1d", "/lib/pkgconfig");
+        encoder.file("421r", "/lib/pkgconfig/xcb.pc");
+        encoder.file("398r", "/lib/pkgconfig/xcb-xkb.pc");
+        encoder.file("1d", "/include");
+        encoder.file("1d", "/include/xcb");
+        encoder.file("10240r", "/include/xcb/xcb.h");
+        encoder.footer(&store_path("xorg.libxcb", "dev", true));
+
+        let mut encoder = Encoder::new(&mut lines);
+        let deep = format!("/share/{}", "a".repeat(200));
+        encoder.file("1d", "/lib");
+        encoder.file("libxcb.so.1.1.0s", "/lib/libxcb.so.1");
+        encoder.file("163416x", "/lib/libxcb.so.1.1.0");
+        encoder.file("1d", &deep);
+        encoder.file("12r", &format!("{deep}/file"));
+        encoder.file("1d", "/lib/x");
+        encoder.footer(&store_path("xorg.libxcb", "out", true));
+
+        let mut encoder = Encoder::new(&mut lines);
+        encoder.file("16r", "/lib/pkgconfig/pthread-stubs.pc");
+        encoder.footer(&store_path("xorg.libxcb", "out", false));
+
+        let mut database = FILE_MAGIC.to_vec();
+        database.extend(FORMAT_VERSION.to_le_bytes());
+        let mut compressed = zstd::Encoder::new(database, 3).unwrap();
+        compressed.write_all(&lines).unwrap();
+        compressed.finish().unwrap()
+    }
+
+    fn reader(
+        database: &[u8],
+    ) -> NixIndexReader<BufReader<zstd::Decoder<'static, BufReader<&[u8]>>>> {
+        NixIndexReader::from_reader(database).unwrap()
+    }
+
+    #[test]
+    fn reads_store_paths_and_files() {
+        let database = generate_database();
+        let store_paths = reader(&database).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(store_paths.len(), 3);
+
+        let (dev, files) = &store_paths[0];
+        assert_eq!(dev.attr(), "xorg.libxcb");
+        assert_eq!(dev.output(), "dev");
+        assert!(dev.toplevel());
+        assert_eq!(
+            dev.path(),
+            "/nix/store/0m2mjrwm4hbq2pnyh9kw6wq1jv4bzzzm-xorg.libxcb-1.0-dev"
+        );
+        assert_eq!(
+            files.iter().map(NixIndexFile::path).collect::<Vec<_>>(),
+            vec![
+                "/lib",
+                "/lib/pkgconfig",
+                "/lib/pkgconfig/xcb.pc",
+                "/lib/pkgconfig/xcb-xkb.pc",
+                "/include",
+                "/include/xcb",
+                "/include/xcb/xcb.h",
+            ]
+        );
+        assert_eq!(
+            files[2].kind(),
+            &NixIndexFileKind::Regular {
+                size: 421,
+                executable: false
+            }
+        );
+        assert_eq!(files[0].kind(), &NixIndexFileKind::Directory);
+
+        let (out, files) = &store_paths[1];
+        assert_eq!(out.output(), "out");
+        assert_eq!(
+            files[1].kind(),
+            &NixIndexFileKind::Symlink {
+                target: "libxcb.so.1.1.0".into()
+            }
+        );
+        assert_eq!(
+            files[2].kind(),
+            &NixIndexFileKind::Regular {
+                size: 163416,
+                executable: true
+            }
+        );
+        assert_eq!(files[4].path(), format!("/share/{}/file", "a".repeat(200)));
+        assert_eq!(files[5].path(), "/lib/x");
+
+        assert!(!store_paths[2].0.toplevel());
+    }
+
+    #[test]
+    fn queries_attribute_output_and_path() {
+        let database = generate_database();
+        let entries = reader(&database)
+            .query(|path| path.ends_with(".pc"))
+            .unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.attr(), entry.output(), entry.path(), entry.toplevel()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("xorg.libxcb", "dev", "/lib/pkgconfig/xcb.pc", true),
+                ("xorg.libxcb", "dev", "/lib/pkgconfig/xcb-xkb.pc", true),
+                (
+                    "xorg.libxcb",
+                    "out",
+                    "/lib/pkgconfig/pthread-stubs.pc",
+                    false
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn indexes_relevant_toplevel_files() {
+        let database = generate_database();
+        let location =
+            std::env::temp_dir().join(format!("debnix-nix-index-{}", std::process::id()));
+        std::fs::write(&location, &database).unwrap();
+        assert!(is_nix_index(&location));
+        let files = NixIndexFiles::open(&location).unwrap();
+        std::fs::remove_file(&location).unwrap();
+
+        assert_eq!(
+            files.providers(&FileKey::PkgConfig("xcb".into())),
+            vec![NixFileProvider::new("xorg.libxcb", "dev")]
+        );
+        assert_eq!(
+            files.providers(&FileKey::SharedLib("libxcb.so.1".into())),
+            vec![NixFileProvider::new("xorg.libxcb", "out")]
+        );
+        assert!(files
+            .providers(&FileKey::PkgConfig("pthread-stubs".into()))
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(NixIndexReader::from_reader(&b"NOPE\x01\0\0\0\0\0\0\0"[..]).is_err());
+        let mut database = FILE_MAGIC.to_vec();
+        database.extend(2u64.to_le_bytes());
+        assert!(NixIndexReader::from_reader(database.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! The report of a discovery run: how many of the debian dependencies
+//! were matched, what was left unmatched, and why pkgs failed.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::error::DebNixError;
+use crate::failures::ErrorSummary;
+use crate::state::{read_outputs_recursive, to_canonical_json, DebNixOutputs};
+
+/// Matches below this confidence are counted as low confidence,
+/// unless a minimum confidence is given.
+pub const LOW_CONFIDENCE: f64 = 0.5;
+/// The amount of entries in the rankings of the report.
+const RANKING_LENGTH: usize = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Markdown,
+    Html,
+}
+
+/// The coverage of the debian dependencies.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Coverage {
+    deps: usize,
+    matched: usize,
+    unmatched: usize,
+    /// Debian infrastructure, that doesn't need a nix dependency.
+    ignored: usize,
+    /// Matched, but below the low confidence threshold.
+    low_confidence: usize,
+}
+
+impl Coverage {
+    fn of(outputs: &DebNixOutputs, low_confidence: f64) -> Self {
+        let mut coverage = Coverage {
+            deps: outputs.applicable_deps().count() + outputs.not_applicable().len(),
+            ignored: outputs.not_applicable().len(),
+            ..Default::default()
+        };
+        for dep in outputs.applicable_deps() {
+            match outputs.map().get(dep) {
+                Some(targets) if !targets.is_empty() => {
+                    coverage.matched += 1;
+                    let confidence = outputs
+                        .matches()
+                        .get(dep)
+                        .map(|info| info.confidence())
+                        .or_else(|| {
+                            targets
+                                .iter()
+                                .filter_map(|target| target.confidence())
+                                .reduce(f64::max)
+                        });
+                    if confidence.is_some_and(|confidence| confidence < low_confidence) {
+                        coverage.low_confidence += 1;
+                    }
+                }
+                _ => coverage.unmatched += 1,
+            }
+        }
+        coverage
+    }
+
+    fn add(&mut self, other: Coverage) {
+        self.deps += other.deps;
+        self.matched += other.matched;
+        self.unmatched += other.unmatched;
+        self.ignored += other.ignored;
+        self.low_confidence += other.low_confidence;
+    }
+
+    /// The matched share of the dependencies, that need a nix dependency.
+    fn ratio(&self) -> f64 {
+        match self.matched + self.unmatched {
+            0 => 1.0,
+            applicable => self.matched as f64 / applicable as f64,
+        }
+    }
+}
+
+/// A name, together with how often it occurred.
+#[derive(Debug, Clone, Serialize)]
+pub struct Count {
+    name: String,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    packages: usize,
+    low_confidence_threshold: f64,
+    coverage: Coverage,
+    ratio: f64,
+    /// `{pkg: coverage}`
+    per_package: BTreeMap<String, Coverage>,
+    /// The debian deps, that were most often left unmatched.
+    top_unmatched: Vec<Count>,
+    /// The nix targets, that were most often matched.
+    top_nix_targets: Vec<Count>,
+    failures: ErrorSummary,
+}
+
+impl RunReport {
+    /// Computes the report over the outputs and the error records of an output directory,
+    /// the outputs of its per package subdirectories are included.
+    pub fn from_output(output: &Path, low_confidence: Option<f64>) -> Result<Self, DebNixError> {
+        let low_confidence = low_confidence.unwrap_or(LOW_CONFIDENCE);
+        let mut coverage = Coverage::default();
+        let mut per_package = BTreeMap::new();
+        let mut unmatched = HashMap::new();
+        let mut nix_targets = HashMap::new();
+        for (location, outputs) in read_outputs_recursive(output, None)? {
+            let pkg = outputs
+                .pkgs_name()
+                .map_or_else(|| location.display().to_string(), String::from);
+            let pkg_coverage = Coverage::of(&outputs, low_confidence);
+            coverage.add(pkg_coverage);
+            per_package.insert(pkg, pkg_coverage);
+            for dep in outputs.applicable_deps() {
+                match outputs.map().get(dep) {
+                    Some(targets) if !targets.is_empty() => {
+                        for target in targets {
+                            *nix_targets.entry(target.attr_path()).or_default() += 1;
+                        }
+                    }
+                    _ => *unmatched.entry(dep.clone()).or_default() += 1,
+                }
+            }
+        }
+        Ok(Self {
+            packages: per_package.len(),
+            low_confidence_threshold: low_confidence,
+            coverage,
+            ratio: coverage.ratio(),
+            per_package,
+            top_unmatched: ranking(unmatched),
+            top_nix_targets: ranking(nix_targets),
+            failures: ErrorSummary::from_output(output)?,
+        })
+    }
+
+    pub fn render(&self, format: ReportFormat) -> Result<String, DebNixError> {
+        match format {
+            ReportFormat::Json => to_canonical_json(self),
+            ReportFormat::Markdown => Ok(self.render_markdown()),
+            ReportFormat::Html => Ok(self.render_html()),
+        }
+    }
+
+    /// The tables of the summary.
+    fn tables(&self) -> Vec<Table> {
+        let coverage_header = vec![
+            "Package",
+            "Deps",
+            "Matched",
+            "Unmatched",
+            "Ignored",
+            "Low Confidence",
+            "Coverage",
+        ];
+        let coverage_row = |name: &str, coverage: &Coverage| {
+            vec![
+                name.to_owned(),
+                coverage.deps.to_string(),
+                coverage.matched.to_string(),
+                coverage.unmatched.to_string(),
+                coverage.ignored.to_string(),
+                coverage.low_confidence.to_string(),
+                format!("{:.1}%", coverage.ratio() * 100.0),
+            ]
+        };
+        let counts = |counts: &[Count]| {
+            counts
+                .iter()
+                .map(|count| vec![count.name.clone(), count.count.to_string()])
+                .collect()
+        };
+        let failures = self
+            .failures
+            .kinds()
+            .iter()
+            .map(|(kind, summary)| {
+                let phases = summary
+                    .phases()
+                    .iter()
+                    .map(|(phase, count)| format!("{phase}: {count}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                vec![kind.clone(), summary.count().to_string(), phases]
+            })
+            .collect();
+        vec![
+            Table {
+                title: "Coverage",
+                header: coverage_header.clone(),
+                rows: vec![coverage_row("all", &self.coverage)],
+            },
+            Table {
+                title: "Most Frequent Unmatched Debian Deps",
+                header: vec!["Debian Dep", "Packages"],
+                rows: counts(&self.top_unmatched),
+            },
+            Table {
+                title: "Most Used Nix Targets",
+                header: vec!["Nix Target", "Matches"],
+                rows: counts(&self.top_nix_targets),
+            },
+            Table {
+                title: "Failures",
+                header: vec!["Kind", "Packages", "Phases"],
+                rows: failures,
+            },
+            Table {
+                title: "Packages",
+                header: coverage_header,
+                rows: self
+                    .per_package
+                    .iter()
+                    .map(|(pkg, coverage)| coverage_row(pkg, coverage))
+                    .collect(),
+            },
+        ]
+    }
+
+    fn overview(&self) -> String {
+        format!(
+            "{} packages discovered, {} failed, {:.1}% of the debian deps matched.",
+            self.packages,
+            self.failures.total(),
+            self.ratio * 100.0
+        )
+    }
+
+    fn render_markdown(&self) -> String {
+        let mut markdown = format!("# Discovery Report\n\n{}\n", self.overview());
+        for Table {
+            title,
+            header,
+            rows,
+        } in self.tables()
+        {
+            let _ = write!(markdown, "\n## {title}\n\n");
+            let _ = writeln!(markdown, "| {} |", header.join(" | "));
+            let _ = writeln!(markdown, "|{}", " --- |".repeat(header.len()));
+            for row in rows {
+                let row = row
+                    .iter()
+                    .map(|cell| cell.replace('|', "\\|"))
+                    .collect::<Vec<_>>();
+                let _ = writeln!(markdown, "| {} |", row.join(" | "));
+            }
+        }
+        markdown
+    }
+
+    fn render_html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Discovery Report</title>\n</head>\n<body>\n\
+             <h1>Discovery Report</h1>\n<p>{}</p>\n",
+            escape_html(&self.overview())
+        );
+        for Table {
+            title,
+            header,
+            rows,
+        } in self.tables()
+        {
+            let _ = writeln!(html, "<h2>{}</h2>\n<table>", escape_html(title));
+            html.push_str(&html_row("th", &header));
+            for row in rows {
+                html.push_str(&html_row("td", &row));
+            }
+            html.push_str("</table>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+/// A table of the markdown, or html summary.
+struct Table {
+    title: &'static str,
+    header: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+fn html_row<S: AsRef<str>>(tag: &str, cells: &[S]) -> String {
+    let mut row = String::from("<tr>");
+    for cell in cells {
+        let _ = write!(row, "<{tag}>{}</{tag}>", escape_html(cell.as_ref()));
+    }
+    row.push_str("</tr>\n");
+    row
+}
+
+/// The most frequent names, ties are ordered by name.
+fn ranking(counts: HashMap<String, usize>) -> Vec<Count> {
+    let mut ranking = counts
+        .into_iter()
+        .map(|(name, count)| Count { name, count })
+        .collect::<Vec<_>>();
+    ranking.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    ranking.truncate(RANKING_LENGTH);
+    ranking
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failures::ErrorRecord;
+    use crate::testing::TempDir;
+
+    /// An output directory of two discovered pkgs, one in its own subdirectory,
+    /// and a failed one.
+    fn output() -> TempDir {
+        let output = TempDir::new("report");
+        output.write(
+            "i3-debnix.json",
+            r#"{
+                "pkgs_name": "i3",
+                "nix_pkg": "i3",
+                "control_file_hash": null,
+                "deb_inputs": ["debhelper-compat", "libxcb1-dev", "libfoo-dev", "libbar-dev"],
+                "nix_inputs": ["libxcb", "bar"],
+                "not_applicable": ["debhelper-compat"],
+                "map": {
+                    "libxcb1-dev": [{ "attr": "xorg.libxcb", "output": "dev", "confidence": 1.0 }],
+                    "libbar-dev": [{ "attr": "bar", "confidence": 0.3 }]
+                }
+            }"#,
+        );
+        output.write(
+            "sway/sway-debnix.json",
+            r#"{
+                "pkgs_name": "sway",
+                "nix_pkg": "sway",
+                "control_file_hash": null,
+                "deb_inputs": ["libxcb1-dev", "libfoo-dev"],
+                "nix_inputs": ["libxcb"],
+                "map": { "libxcb1-dev": "xorg.libxcb.dev" },
+                "matches": {
+                    "libxcb1-dev": { "strategy": "exact", "pass": "direct", "confidence": 0.95 }
+                }
+            }"#,
+        );
+        output.write("journal.json", "{}");
+        let error_dir = output.path().join("error");
+        std::fs::create_dir_all(&error_dir).unwrap();
+        ErrorRecord::new("broken", &DebNixError::Redirect("gone".into()))
+            .write(&error_dir)
+            .unwrap();
+        output
+    }
+
+    fn names(counts: &[Count]) -> Vec<(&str, usize)> {
+        counts
+            .iter()
+            .map(|count| (count.name.as_str(), count.count))
+            .collect()
+    }
+
+    #[test]
+    fn report_of_an_output_directory() {
+        let output = output();
+        let report = RunReport::from_output(output.path(), None).unwrap();
+        assert_eq!(report.packages, 2);
+        assert_eq!(report.low_confidence_threshold, LOW_CONFIDENCE);
+        let coverage = report.coverage;
+        assert_eq!(
+            (
+                coverage.deps,
+                coverage.matched,
+                coverage.unmatched,
+                coverage.ignored,
+                coverage.low_confidence
+            ),
+            (6, 3, 2, 1, 1)
+        );
+        assert_eq!(report.ratio, 0.6);
+        assert_eq!(
+            report.per_package.keys().collect::<Vec<_>>(),
+            ["i3", "sway"]
+        );
+        assert_eq!(names(&report.top_unmatched), [("libfoo-dev", 2)]);
+        assert_eq!(
+            names(&report.top_nix_targets),
+            [("xorg.libxcb.dev", 2), ("bar", 1)]
+        );
+        assert_eq!(report.failures.total(), 1);
+
+        // a lower threshold
+        let report = RunReport::from_output(output.path(), Some(0.2)).unwrap();
+        assert_eq!(report.coverage.low_confidence, 0);
+    }
+
+    #[test]
+    fn render_markdown() {
+        let output = output();
+        let report = RunReport::from_output(output.path(), None).unwrap();
+        let markdown = report.render(ReportFormat::Markdown).unwrap();
+        assert!(markdown.starts_with(
+            "# Discovery Report\n\n2 packages discovered, 1 failed, 60.0% of the debian deps matched.\n"
+        ));
+        assert!(markdown.contains(
+            "## Coverage\n\n\
+             | Package | Deps | Matched | Unmatched | Ignored | Low Confidence | Coverage |\n\
+             | --- | --- | --- | --- | --- | --- | --- |\n\
+             | all | 6 | 3 | 2 | 1 | 1 | 60.0% |\n"
+        ));
+        assert!(markdown.contains("| libfoo-dev | 2 |\n"));
+        assert!(markdown.contains("| redirect | 1 |  |\n"));
+        assert!(markdown.contains("| sway | 2 | 1 | 1 | 0 | 0 | 50.0% |\n"));
+    }
+
+    #[test]
+    fn render_html() {
+        let output = output();
+        let report = RunReport::from_output(output.path(), None).unwrap();
+        let html = report.render(ReportFormat::Html).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>\n"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+        assert!(html.contains("<h2>Most Used Nix Targets</h2>\n<table>"));
+        assert!(html.contains("<tr><td>xorg.libxcb.dev</td><td>2</td></tr>\n"));
+        assert_eq!(
+            escape_html(r#"<a href="x">&</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn render_json() {
+        let output = output();
+        let report = RunReport::from_output(output.path(), None).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&report.render(ReportFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["packages"], 2);
+        assert_eq!(json["ratio"], 0.6);
+        assert_eq!(json["per_package"]["sway"]["unmatched"], 1);
+        assert_eq!(json["failures"]["total"], 1);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::matcher::NixTarget;
+use crate::state::DebNixOutputs;
+
+/// A debian pkg, that a nix attribute was matched with,
+/// together with the provenance of the match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebCandidate {
+    deb: String,
+    /// The output of the nix attribute, that the debian pkg maps to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confidence: Option<f64>,
+    /// The strategy, that produced the match, `toplevel` for the pkg itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    strategy: Option<String>,
+    /// The output file, or map, that the match was read from.
+    source: String,
+}
+
+impl DebCandidate {
+    pub fn deb(&self) -> &str {
+        self.deb.as_ref()
+    }
+
+    pub fn confidence(&self) -> Option<f64> {
+        self.confidence
+    }
+}
+
+/// The debian names of nix attributes: `{nix-attr: [deb-candidate]}`,
+/// the reverse of the generated maps.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReverseIndex {
+    attrs: BTreeMap<String, Vec<DebCandidate>>,
+}
+
+impl ReverseIndex {
+    /// Adds the matches of a single discovered pkg.
+    pub fn add_outputs(&mut self, source: &str, outputs: &DebNixOutputs) {
+        if let (Some(deb), Some(nix_pkg)) = (outputs.pkgs_name(), outputs.nix_pkg()) {
+            let target = NixTarget::from_attr_path(nix_pkg, None);
+            self.insert(&target, deb, None, Some("toplevel"), source);
+        }
+        for (deb, targets) in outputs.map() {
+            let info = outputs.matches().get(deb);
+            for target in targets {
+                // outputs in the flat format only record the confidence with the match
+                let confidence = target
+                    .confidence()
+                    .or_else(|| info.map(|info| info.confidence()));
+                let strategy = info.map(|info| info.strategy());
+                self.insert(target, deb, confidence, strategy, source);
+            }
+        }
+    }
+
+    /// Adds the entries of a map, either in the current, or in the flat format.
+    pub fn add_map(&mut self, source: &str, map: &BTreeMap<String, Vec<NixTarget>>) {
+        for (deb, targets) in map {
+            for target in targets {
+                self.insert(target, deb, target.confidence(), None, source);
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        target: &NixTarget,
+        deb: &str,
+        confidence: Option<f64>,
+        strategy: Option<&str>,
+        source: &str,
+    ) {
+        let candidate = DebCandidate {
+            deb: deb.into(),
+            output: target.output().map(String::from),
+            confidence,
+            strategy: strategy.map(String::from),
+            source: source.into(),
+        };
+        let candidates = self.attrs.entry(target.attr().into()).or_default();
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+
+    /// Orders the candidates of every attribute, so that the index is deterministic.
+    pub fn sort(&mut self) {
+        for candidates in self.attrs.values_mut() {
+            candidates.sort_by(|a, b| {
+                a.deb
+                    .cmp(&b.deb)
+                    .then_with(|| a.output.cmp(&b.output))
+                    .then_with(|| a.source.cmp(&b.source))
+            });
+        }
+    }
+
+    /// The debian candidates of an attribute, the most confident ones first.
+    /// Accepts `pkgs.libxkbcommon`, as well as a specific output: `libxkbcommon.dev`.
+    pub fn lookup(&self, attr_path: &str) -> Vec<&DebCandidate> {
+        let attr_path = attr_path.strip_prefix("pkgs.").unwrap_or(attr_path);
+        let mut candidates = match self.attrs.get(attr_path) {
+            Some(candidates) => candidates.iter().collect::<Vec<&DebCandidate>>(),
+            None => {
+                let target = NixTarget::from_attr_path(attr_path, None);
+                self.attrs
+                    .get(target.attr())
+                    .into_iter()
+                    .flatten()
+                    .filter(|candidate| candidate.output.as_deref() == target.output())
+                    .collect()
+            }
+        };
+        candidates.sort_by(|a, b| {
+            b.confidence
+                .unwrap_or(1.0)
+                .total_cmp(&a.confidence.unwrap_or(1.0))
+                .then_with(|| a.deb.cmp(&b.deb))
+        });
+        candidates
+    }
+}
+
+/// The name of the source of a match, relative to its directory.
+pub(crate) fn source_name(location: &Path) -> String {
+    location.file_name().map_or_else(
+        || location.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debs<'a>(candidates: &[&'a DebCandidate]) -> Vec<&'a str> {
+        candidates.iter().map(|candidate| candidate.deb()).collect()
+    }
+
+    fn outputs(json: &str) -> DebNixOutputs {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn lookup_strips_the_pkgs_prefix() {
+        let mut index = ReverseIndex::default();
+        let map = BTreeMap::from([(
+            String::from("libxkbcommon-dev"),
+            vec![NixTarget::from_attr_path("libxkbcommon.dev", Some(0.95))],
+        )]);
+        index.add_map("debnix.json", &map);
+        assert_eq!(
+            debs(&index.lookup("pkgs.libxkbcommon")),
+            ["libxkbcommon-dev"]
+        );
+        assert_eq!(debs(&index.lookup("libxkbcommon")), ["libxkbcommon-dev"]);
+        assert_eq!(
+            debs(&index.lookup("pkgs.libxkbcommon.dev")),
+            ["libxkbcommon-dev"]
+        );
+        assert!(index.lookup("libxkbcommon.lib").is_empty());
+        assert!(index.lookup("pkgs.libfoo").is_empty());
+    }
+
+    #[test]
+    fn multi_target_entries_reverse_to_every_target() {
+        let mut index = ReverseIndex::default();
+        index.add_outputs(
+            "qtbase-opensource-src-debnix.json",
+            &outputs(
+                r#"{
+                    "pkgs_name": "qtbase-opensource-src",
+                    "nix_pkg": "qt5.qtbase",
+                    "control_file_hash": null,
+                    "deb_inputs": ["qtbase5-dev", "libxcb1-dev"],
+                    "nix_inputs": ["libxcb"],
+                    "map": {
+                        "qtbase5-dev": [
+                            { "attr": "qt5.qtbase", "output": "dev" },
+                            { "attr": "qt5.qttools", "output": "dev" }
+                        ],
+                        "libxcb1-dev": "xorg.libxcb.dev"
+                    },
+                    "matches": {
+                        "qtbase5-dev": { "strategy": "qt5", "pass": "direct", "confidence": 0.9 },
+                        "libxcb1-dev": { "strategy": "exact", "pass": "direct", "confidence": 1.0 }
+                    }
+                }"#,
+            ),
+        );
+        index.sort();
+        // the toplevel pkg and the dev output of the same attribute
+        assert_eq!(
+            debs(&index.lookup("qt5.qtbase")),
+            ["qtbase-opensource-src", "qtbase5-dev"]
+        );
+        assert_eq!(debs(&index.lookup("qt5.qttools.dev")), ["qtbase5-dev"]);
+        let libxcb = index.lookup("xorg.libxcb");
+        assert_eq!(debs(&libxcb), ["libxcb1-dev"]);
+        // the flat format records the confidence with the match
+        assert_eq!(libxcb[0].confidence(), Some(1.0));
+        assert_eq!(libxcb[0].strategy.as_deref(), Some("exact"));
+        assert_eq!(libxcb[0].source, "qtbase-opensource-src-debnix.json");
+    }
+
+    #[test]
+    fn candidates_are_ordered_by_confidence() {
+        let mut index = ReverseIndex::default();
+        let map = BTreeMap::from([
+            (
+                String::from("libgtk-3-dev"),
+                vec![NixTarget::from_attr_path("gtk3.dev", Some(0.6))],
+            ),
+            (
+                String::from("libgtk3-dev"),
+                vec![NixTarget::from_attr_path("gtk3.dev", Some(0.9))],
+            ),
+        ]);
+        index.add_map("debnix.json", &map);
+        index.add_map("debnix.json", &map);
+        assert_eq!(
+            debs(&index.lookup("gtk3.dev")),
+            ["libgtk3-dev", "libgtk-3-dev"]
+        );
+    }
+}
@@ -1,15 +1,32 @@
+use crate::aggregate::{Aggregate, ConflictPolicy};
 use crate::cli::CliArgs;
-use crate::deb::{get_debian_pkg_outputs, ControlFileApi};
-use crate::error::DebNixError;
-use crate::matcher::match_libs;
-use crate::nix::{get_drv_inputs, NIX_ATTRIBUTES_REVERSED};
+use crate::deb::packages::PackagesIndex;
+use crate::deb::{debian_redirect_with_retry, get_debian_pkg_outputs, ControlFileApi, DebRole};
+use crate::elf::{Shlibs, SonameResolver};
+use crate::error::{DebNixError, InPhase, Phase};
+use crate::files::{open_nix_file_index, ContentsIndex, FileMatcher};
+use crate::journal::Journal;
+use crate::limits::{HTTP, NIX};
+use crate::matcher::fuzzy::RankedCandidate;
+use crate::matcher::pipeline::{Pipeline, PipelineConfig};
+use crate::matcher::{best_target, deserialize_targets, match_libs, MatchInfo, NixTarget};
+use crate::nix::{get_drv_inputs, get_drv_path, NixInput, NIX_ATTRIBUTES_REVERSED};
+use crate::reverse::{source_name, ReverseIndex};
+use control_file::ControlFile;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::{fs::File, io::Write, path::Path};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::{
+    ffi::OsString,
+    fs::{create_dir_all, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 /// outputs/toplevel-debnix.json
-/// HashMap {deb-lib: nix-lib}
-/// outputs/toplevel-nixdeb.json
+/// BTreeMap {deb-lib: [nix-lib]}
+/// outputs/maps/debnix-nixdeb.json
+/// BTreeMap {nix-attr: [deb-candidate]}
 ///
 /// outputs/i3/i3-debnix.json
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,33 +36,161 @@ pub struct DebNixOutputs {
     // deb_name: Option<String>,
     nix_pkg: Option<String>,
     control_file_hash: Option<String>,
+    /// The derivations, that the nix inputs were read from: `{attr: drv-path}`.
+    #[serde(default)]
+    nix_drvs: BTreeMap<String, String>,
+    /// The attributes, that had no derivation when the pkg was discovered.
+    #[serde(default)]
+    nix_missing: Vec<String>,
     deb_inputs: Vec<String>,
     nix_inputs: Vec<String>,
-    map: HashMap<String, String>,
+    /// The nix targets of every matched debian input,
+    /// maps in the former flat format `{deb-lib: nix-lib}` are read as well.
+    #[serde(deserialize_with = "deserialize_targets")]
+    map: BTreeMap<String, Vec<NixTarget>>,
+    /// The role of the debian binary packages, e.g. `dev` for `libxcb1-dev`.
+    #[serde(default)]
+    deb_roles: BTreeMap<String, DebRole>,
+    /// The strategy and confidence of every match in the map.
+    #[serde(default)]
+    matches: BTreeMap<String, MatchInfo>,
+    /// Ranked candidates for the debian inputs, that couldn't be matched.
+    #[serde(default)]
+    candidates: BTreeMap<String, Vec<RankedCandidate>>,
+    /// Debian infrastructure (`debhelper-compat`, `dh-*`, ...),
+    /// that is not applicable to nix, as opposed to being unmatched.
+    #[serde(default)]
+    not_applicable: Vec<String>,
+    /// Virtual debian inputs and the concrete pkgs, that they were matched through.
+    #[serde(default)]
+    virtuals: BTreeMap<String, String>,
+    /// Debian inputs, that couldn't be redirected to their source pkg, with the reason.
+    #[serde(default)]
+    redirect_failed: BTreeMap<String, String>,
+}
+
+impl DebNixOutputs {
+    pub(crate) fn pkgs_name(&self) -> Option<&str> {
+        self.pkgs_name.as_deref()
+    }
+
+    pub(crate) fn nix_pkg(&self) -> Option<&str> {
+        self.nix_pkg.as_deref()
+    }
+
+    pub(crate) fn map(&self) -> &BTreeMap<String, Vec<NixTarget>> {
+        &self.map
+    }
+
+    pub(crate) fn matches(&self) -> &BTreeMap<String, MatchInfo> {
+        &self.matches
+    }
+
+    /// The debian inputs, that are debian infrastructure.
+    pub(crate) fn not_applicable(&self) -> &[String] {
+        &self.not_applicable
+    }
+
+    /// The debian inputs, that are not debian infrastructure.
+    pub(crate) fn applicable_deps(&self) -> impl Iterator<Item = &String> {
+        self.deb_inputs
+            .iter()
+            .filter(|dep| !self.not_applicable.contains(dep))
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct State {
-    map: Option<HashMap<String, String>>,
+    map: Option<BTreeMap<String, Vec<NixTarget>>>,
+    map_location: Option<String>,
     discover: Option<usize>,
     discover_start: Option<usize>,
     timeout: Option<usize>,
     output: Option<String>,
+    jobs: usize,
+    resume: bool,
+    retry_failed: bool,
+    refresh: bool,
+    recursive: bool,
+    max_depth: usize,
+    files: Option<Arc<FileMatcher>>,
+    shlibs: Option<Shlibs>,
+    pipeline: Pipeline,
 }
 
 impl State {
     pub(crate) fn from_opts(opts: CliArgs) -> Result<Self, DebNixError> {
+        // the journal of a sweep lives inside of the output directory
+        if opts.output().is_none() {
+            let flag = [
+                ("--discover", opts.discover().is_some()),
+                ("--discover-from", opts.discover_from().is_some()),
+                ("--recursive", opts.recursive()),
+                ("--retry-failed", opts.retry_failed()),
+                ("--resume", opts.resume()),
+                ("--refresh", opts.refresh()),
+            ]
+            .into_iter()
+            .find_map(|(flag, set)| set.then_some(flag));
+            if let Some(flag) = flag {
+                return Err(DebNixError::Config(format!(
+                    "`{flag}` needs an output directory (`--output`)."
+                )));
+            }
+        }
         let map = if let Some(location) = opts.map() {
             Some(open_map(location)?)
         } else {
             None
         };
+        let files = match (opts.contents(), opts.nix_files()) {
+            (Some(contents), Some(nix_files)) => Some(Arc::new(FileMatcher::new(
+                ContentsIndex::open(Path::new(contents))?,
+                open_nix_file_index(Path::new(nix_files))?,
+            ))),
+            _ => None,
+        };
+        let shlibs = if let Some(location) = opts.shlibs() {
+            Some(Shlibs::open(Path::new(location))?)
+        } else {
+            None
+        };
+        let mut config = if let Some(location) = opts.match_config() {
+            PipelineConfig::open(location)?
+        } else {
+            PipelineConfig::default()
+        };
+        config.disable(opts.disable_strategy().iter().cloned());
+        config.set_global(opts.global());
+        config.override_infrastructure(opts.ignore().to_vec(), opts.unignore().to_vec());
+        if let Some(amount) = opts.fuzzy_candidates() {
+            config.set_fuzzy_candidates(amount);
+        }
+        let packages = if let Some(location) = opts.packages() {
+            Some(Arc::new(PackagesIndex::open(Path::new(location))?))
+        } else {
+            None
+        };
+        HTTP.set_limit(opts.http_jobs());
+        NIX.set_limit(opts.nix_jobs());
+        let pipeline = Pipeline::new(&config, files.clone())?.with_packages(packages);
+        debug!("Matching pipeline: {:?}", pipeline);
         Ok(Self {
             map,
+            map_location: opts.map().cloned(),
             discover: opts.discover(),
             discover_start: opts.discover_start(),
             timeout: opts.timeout(),
             output: opts.output(),
+            jobs: opts.jobs(),
+            resume: opts.resume(),
+            retry_failed: opts.retry_failed(),
+            refresh: opts.refresh(),
+            recursive: opts.recursive(),
+            max_depth: opts.max_depth(),
+            files,
+            shlibs,
+            pipeline,
         })
     }
 
@@ -53,7 +198,7 @@ impl State {
         // let outputs = discover(pkgs.clone(), state.map.clone())?;
         let outputs = self.discover_pkg(pkg_name)?;
         if let Some(destination) = &self.output {
-            let serialized = serde_json::to_string(&outputs)?;
+            let serialized = to_canonical_json(&outputs)?;
             let mut file = File::create(destination)
                 .map_err(|e| DebNixError::IoPath(format!("{e}: {destination}")))?;
             file.write_all(serialized.as_bytes())?;
@@ -61,64 +206,162 @@ impl State {
         Ok(())
     }
 
+    /// Translates the sonames a binary or `.deb` needs into nix dependencies,
+    /// and writes them to the output, or stdout.
+    pub fn discover_elf(&self, location: &str) -> Result<(), DebNixError> {
+        let files = self.files.as_ref().ok_or_else(|| {
+            DebNixError::Elf("Soname matching needs `--contents` and `--nix-files`.".into())
+        })?;
+        let outputs =
+            SonameResolver::new(files, self.shlibs.as_ref()).resolve(Path::new(location))?;
+        let serialized = to_canonical_json(&outputs)?;
+        if let Some(destination) = &self.output {
+            let mut file = File::create(destination)
+                .map_err(|e| DebNixError::IoPath(format!("{e}: {destination}")))?;
+            file.write_all(serialized.as_bytes())?;
+        } else {
+            print!("{serialized}");
+        }
+        Ok(())
+    }
+
     /// This is the main discovery function, for a single package.
     pub fn discover_pkg(&self, pkg: String) -> Result<DebNixOutputs, DebNixError> {
-        // Prepare possible names for nix pkgs definitions.
-        let mut nix_inputs = vec![];
         let mut nix_pkg = None;
 
         if let Some(attr_path) = NIX_ATTRIBUTES_REVERSED.get(&pkg) {
             nix_pkg = Some(attr_path.attrpath.clone()).flatten();
         }
 
-        nix_inputs.push(pkg.clone());
-        let mut unwrapped = pkg.clone();
-        unwrapped.push_str("-unwrapped");
-        nix_inputs.push(unwrapped);
-
-        if let Some(map) = self.map() {
-            // Lookup in the provided map for an associated pkg name
-            if let Some(pkg) = map.get(&pkg) {
-                nix_inputs.push(pkg.to_string())
-            }
-        }
-        // Get the debian pkg outputs
-        if let Ok(deb_inputs) = get_debian_pkg_outputs(&pkg) {
-            nix_inputs.extend(deb_inputs);
-        };
-        let input_names = drv_inputs_from_pkgs(nix_inputs)?;
+        let nix_inputs = self.nix_lookups(&pkg);
+        let DrvInputs {
+            inputs: drv_inputs,
+            drvs: nix_drvs,
+            missing: nix_missing,
+        } = drv_inputs_from_pkgs(nix_inputs).in_phase(Phase::NixEval)?;
+        let mut input_names = drv_inputs
+            .iter()
+            .map(|input| input.pname().to_owned())
+            .collect::<Vec<String>>();
+        input_names.dedup();
         info!("{:?}", input_names);
         info!("Nix Inputs Amount: {:?}", input_names.len());
 
         // Get the control file api for the specific package
         info!("Getting Control file for {:?}", &pkg);
-        let control_file_api = ControlFileApi::from_redirect(&pkg)?;
-        let control_file_hash =
-            String::from(control_file_api.checksum().ok_or_else(|| {
-                DebNixError::DebControl("Couldn't get Control file Hash.".into())
-            })?);
-        let mut deb_deps = control_file_api.get_debian_deps()?;
+        let source = debian_redirect_with_retry(&pkg).in_phase(Phase::Redirect)?;
+        let control_file_api = ControlFileApi::new(&source).in_phase(Phase::ControlFetch)?;
+        let control_file_hash = control_file_api
+            .checksum()
+            .cloned()
+            .ok_or_else(|| DebNixError::DebControl("Couldn't get Control file Hash.".into()))
+            .in_phase(Phase::ControlFetch)?;
+        let control_file = control_file_api
+            .download_control_file()
+            .in_phase(Phase::ControlFetch)?;
+        let mut deb_deps = ControlFile::from_str(&control_file)
+            .and_then(|control| control.get_dependencies())
+            .in_phase(Phase::Parse)?;
         deb_deps.sort();
         deb_deps.dedup();
         info!("{:?}", &deb_deps);
         info!("Debian Dependency Amount: {:?}", &deb_deps.len());
-        let result = match_libs(deb_deps.clone(), input_names.clone())?;
-        info!("Amount: {:?}", result.keys().len());
+        let result =
+            match_libs(deb_deps.clone(), &drv_inputs, &self.pipeline).in_phase(Phase::Matching)?;
+        info!("Amount: {:?}", result.map.keys().len());
+        let deb_roles = deb_deps
+            .iter()
+            .map(|dep| (dep.clone(), DebRole::from_name(dep)))
+            .collect();
         Ok(DebNixOutputs {
             pkgs_name: Some(pkg),
             nix_pkg,
             control_file_hash: Some(control_file_hash),
+            nix_drvs,
+            nix_missing,
             deb_inputs: deb_deps,
             nix_inputs: input_names,
-            map: result,
+            map: result.map,
+            deb_roles,
+            matches: result.info,
+            candidates: result.candidates,
+            not_applicable: result.not_applicable,
+            virtuals: result.virtuals,
+            redirect_failed: result.redirect_failed,
         })
     }
 
+    /// Looks up the debian names of a nix attribute in the discovered outputs,
+    /// either of the output directory or of `./outputs` and their subdirectories,
+    /// and in the input map.
+    pub fn lookup_nix_attr(&self, attr_path: &str) -> Result<(), DebNixError> {
+        let location = self
+            .output
+            .as_deref()
+            .filter(|output| Path::new(output).is_dir())
+            .unwrap_or("./outputs");
+        // the input map, and the directory it is in, are not read as outputs
+        let map = self.map_location.as_deref().map(Path::new);
+        let mut index = ReverseIndex::default();
+        for (path, outputs) in read_outputs_recursive(Path::new(location), map)? {
+            index.add_outputs(&source_name(&path), &outputs);
+        }
+        if let (Some(map), Some(location)) = (&self.map, &self.map_location) {
+            index.add_map(&source_name(Path::new(location)), map);
+        }
+        let candidates = BTreeMap::from([(attr_path, index.lookup(attr_path))]);
+        print!("{}", to_canonical_json(&candidates)?);
+        Ok(())
+    }
+
+    /// Prepares the possible names of the nix pkg definitions of a debian pkg.
+    fn nix_lookups(&self, pkg: &str) -> Vec<String> {
+        let mut nix_inputs = vec![pkg.to_owned()];
+        let mut unwrapped = pkg.to_owned();
+        unwrapped.push_str("-unwrapped");
+        nix_inputs.push(unwrapped);
+
+        if let Some(map) = self.map() {
+            // Lookup in the provided map for an associated pkg name
+            if let Some(targets) = map.get(pkg) {
+                nix_inputs.extend(targets.iter().map(|target| target.attr().to_owned()))
+            }
+        }
+        // Get the debian pkg outputs
+        if let Ok(deb_inputs) = get_debian_pkg_outputs(pkg) {
+            nix_inputs.extend(deb_inputs);
+        };
+        nix_inputs
+    }
+
+    /// Checks if the debian control file, or one of the nix derivations of a discovered
+    /// pkg changed since it was discovered, and returns what changed.
+    /// Attributes, that had no derivation, are looked up again.
+    pub fn changed_since(&self, outputs: &DebNixOutputs) -> Result<Option<String>, DebNixError> {
+        let Some(pkg) = outputs.pkgs_name() else {
+            return Ok(None);
+        };
+        let control_file_api = ControlFileApi::from_redirect(pkg)?;
+        changes_of(
+            outputs,
+            control_file_api.checksum(),
+            self.nix_lookups(pkg),
+            |attr| match get_drv_path(attr) {
+                Ok(drv_path) => Ok(Some(drv_path)),
+                Err(DebNixError::Nix(e)) => {
+                    debug!("{}", e);
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            },
+        )
+    }
+
     pub(crate) fn output(&self) -> Option<&String> {
         self.output.as_ref()
     }
 
-    pub(crate) fn map(&self) -> Option<&HashMap<String, String>> {
+    pub(crate) fn map(&self) -> Option<&BTreeMap<String, Vec<NixTarget>>> {
         self.map.as_ref()
     }
 
@@ -126,6 +369,34 @@ impl State {
         self.discover
     }
 
+    pub(crate) fn resume(&self) -> bool {
+        self.resume
+    }
+
+    pub(crate) fn retry_failed(&self) -> bool {
+        self.retry_failed
+    }
+
+    pub(crate) fn refresh(&self) -> bool {
+        self.refresh
+    }
+
+    pub(crate) fn recursive(&self) -> bool {
+        self.recursive
+    }
+
+    pub(crate) fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub(crate) fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    pub(crate) fn jobs(&self) -> usize {
+        self.jobs
+    }
+
     pub(crate) fn timeout(&self) -> Option<usize> {
         self.timeout
     }
@@ -135,20 +406,34 @@ impl State {
     }
 }
 
+/// The inputs of the derivations of a pkg, together with the lookups they came from.
+struct DrvInputs {
+    inputs: Vec<NixInput>,
+    /// `{attr: drv-path}`
+    drvs: BTreeMap<String, String>,
+    /// The attributes, that have no derivation.
+    missing: Vec<String>,
+}
+
 /// Try to get the inputs of a derivation from multiple possible pkg names
 /// TODO: pass in a vec of possible pkgs from outside.
-fn drv_inputs_from_pkgs(pkgs: Vec<String>) -> Result<Vec<String>, DebNixError> {
+/// The paths of the derivations, that the inputs were read from, are returned as well.
+fn drv_inputs_from_pkgs(pkgs: Vec<String>) -> Result<DrvInputs, DebNixError> {
     let mut inputs = vec![];
+    let mut drv_paths = BTreeMap::new();
+    let mut missing = vec![];
 
     for pkg in pkgs {
         let input_names = get_drv_inputs(&pkg);
         match input_names {
-            Ok(names) => {
+            Ok((drv_path, names)) => {
+                drv_paths.insert(pkg, drv_path);
                 inputs.extend(names);
             }
             Err(e) => match e {
                 DebNixError::Nix(e) => {
                     debug!("{}", e);
+                    missing.push(pkg);
                 }
                 e => return Err(e),
             },
@@ -156,51 +441,518 @@ fn drv_inputs_from_pkgs(pkgs: Vec<String>) -> Result<Vec<String>, DebNixError> {
     }
     inputs.sort();
     inputs.dedup();
-    Ok(inputs)
+    missing.sort();
+    missing.dedup();
+    Ok(DrvInputs {
+        inputs,
+        drvs: drv_paths,
+        missing,
+    })
 }
 
-/// Reads the provided output json's and creates a single json file
-/// for easy key value lookups.
-pub fn create_output_map(_location: &str) -> Result<(), DebNixError> {
+/// Reads the output json's of the input directories and their subdirectories,
+/// and creates a single json file for easy key value lookups: `{deb-lib: [nix-target]}`.
+/// Matches below the minimum confidence are left out,
+/// matches without a recorded confidence are always included.
+/// For compatibility the most confident target of every debian pkg
+/// is exported in the former flat format `{deb-lib: nix-lib}` as well,
+/// next to the map as `<map>-flat.json`, together with the reverse map `<map>-nixdeb.json`.
+/// A location, that is a directory, receives the map as `debnix.json`.
+/// Outputs, that disagree on the targets of a debian pkg, are resolved
+/// through the policy, and listed in `<map>-conflicts.json`.
+pub fn create_output_map(
+    inputs: &[PathBuf],
+    location: &Path,
+    min_confidence: Option<f64>,
+    policy: ConflictPolicy,
+    curated: Option<&BTreeMap<String, Vec<NixTarget>>>,
+) -> Result<(), DebNixError> {
+    let location = if location.is_dir() || location.to_string_lossy().ends_with('/') {
+        location.join("debnix.json")
+    } else {
+        location.to_path_buf()
+    };
+    let destination = match location.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    create_dir_all(&destination)
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", destination.display())))?;
+    let mut outputs = vec![];
+    for input in inputs {
+        outputs.extend(read_outputs_recursive(input, Some(&location))?);
+    }
+    // inputs, that are given twice, or nested in each other are read once
+    let mut read = HashSet::new();
+    outputs.retain(|(path, _)| read.insert(path.canonicalize().unwrap_or_else(|_| path.clone())));
+
+    let mut aggregate = Aggregate::default();
+    let mut reverse = ReverseIndex::default();
+    for (path, deserialized) in outputs {
+        let source = source_name(&path);
+        reverse.add_outputs(&source, &deserialized);
+        if let Some(deb_name) = deserialized.pkgs_name {
+            if let Some(nix_name) = deserialized.nix_pkg {
+                aggregate.vote(
+                    &deb_name,
+                    NixTarget::from_attr_path(&nix_name, None),
+                    &source,
+                );
+            }
+        }
+        for (key, targets) in deserialized.map {
+            let info = deserialized.matches.get(&key);
+            for mut target in targets {
+                let confidence = target
+                    .confidence()
+                    .or_else(|| info.map(|info| info.confidence()));
+                if let (Some(min_confidence), Some(confidence)) = (min_confidence, confidence) {
+                    if confidence < min_confidence {
+                        debug!("Skipping low confidence match: {} {:?}", key, target);
+                        continue;
+                    }
+                }
+                target.raise_confidence(confidence);
+                aggregate.vote(&key, target, &source);
+            }
+        }
+    }
+    let (result, conflicts) = aggregate.resolve(policy, curated)?;
+    // write the result map to the target location
+    write_artifact(&location, &result)?;
+    let flat = result
+        .iter()
+        .filter_map(|(deb, targets)| Some((deb, best_target(targets)?.attr_path())))
+        .collect::<BTreeMap<&String, String>>();
+    let [_, flat_location, reverse_location, conflicts_location] = map_artifacts(&location);
+    write_artifact(&flat_location, &flat)?;
+    // the reverse map, with the provenance of every debian name
+    reverse.sort();
+    write_artifact(&reverse_location, &reverse)?;
+    // the disagreeing entries, for review
+    write_artifact(&conflicts_location, &conflicts)?;
+    Ok(())
+}
+
+/// The files, that are generated for a map: the map itself, and next to it
+/// `<map>-flat.json`, `<map>-nixdeb.json` and `<map>-conflicts.json`.
+/// The artifacts of different maps can share a directory.
+fn map_artifacts(location: &Path) -> [PathBuf; 4] {
+    let stem = location
+        .file_stem()
+        .map_or_else(|| "debnix".into(), |stem| stem.to_string_lossy());
+    [
+        location.to_path_buf(),
+        location.with_file_name(format!("{stem}-flat.json")),
+        location.with_file_name(format!("{stem}-nixdeb.json")),
+        location.with_file_name(format!("{stem}-conflicts.json")),
+    ]
+}
+
+/// Compares a discovered pkg with the current checksum of its control file,
+/// and the current nix lookups, returns what changed.
+/// The derivations are only looked up through `drv_path` as long as nothing changed,
+/// `None` if an attribute has no derivation.
+fn changes_of(
+    outputs: &DebNixOutputs,
+    checksum: Option<&String>,
+    mut lookups: Vec<String>,
+    drv_path: impl Fn(&str) -> Result<Option<String>, DebNixError>,
+) -> Result<Option<String>, DebNixError> {
+    if checksum != outputs.control_file_hash.as_ref() {
+        return Ok(Some("control file changed".into()));
+    }
+    if outputs.nix_drvs.is_empty() && outputs.nix_missing.is_empty() {
+        // discovered, before the lookups were recorded
+        return Ok(Some("no recorded derivations".into()));
+    }
+    lookups.sort();
+    lookups.dedup();
+    let mut recorded = outputs
+        .nix_drvs
+        .keys()
+        .chain(&outputs.nix_missing)
+        .cloned()
+        .collect::<Vec<String>>();
+    recorded.sort();
+    recorded.dedup();
+    if lookups != recorded {
+        return Ok(Some("nix lookups changed".into()));
+    }
+    for (attr, recorded) in &outputs.nix_drvs {
+        match drv_path(attr)? {
+            Some(current) if current == *recorded => {}
+            Some(_) => return Ok(Some(format!("derivation of {attr} changed"))),
+            None => return Ok(Some(format!("derivation of {attr} vanished"))),
+        }
+    }
+    for attr in &outputs.nix_missing {
+        if drv_path(attr)?.is_some() {
+            return Ok(Some(format!("derivation of {attr} appeared")));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the discovered outputs of a directory and its per package subdirectories.
+/// The error records, and the directory of the generated map are left out,
+/// if the map is generated into one of the directories, its artifacts are left out.
+pub(crate) fn read_outputs_recursive(
+    location: &Path,
+    map: Option<&Path>,
+) -> Result<Vec<(PathBuf, DebNixOutputs)>, DebNixError> {
+    let maps = map.map(|map| match map.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    });
+    let artifacts = match (map, maps) {
+        (Some(map), Some(maps)) if same_dir(location, maps) => map_artifacts(map)
+            .into_iter()
+            .filter_map(|artifact| artifact.file_name().map(OsString::from))
+            .collect(),
+        _ => vec![],
+    };
+    let mut result = read_outputs_where(location, |path| {
+        !path
+            .file_name()
+            .is_some_and(|name| artifacts.iter().any(|artifact| artifact == name))
+    })?;
+    let mut entries = location
+        .read_dir()
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    entries.sort();
+    for entry in entries {
+        if entry.file_name().is_some_and(|name| name == "error")
+            || maps.is_some_and(|maps| same_dir(&entry, maps))
+        {
+            continue;
+        }
+        result.extend(read_outputs_recursive(&entry, map)?);
+    }
+    Ok(result)
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Reads the discovered outputs of a directory,
+/// files that are not outputs are skipped.
+pub(crate) fn read_outputs(location: &Path) -> Result<Vec<(PathBuf, DebNixOutputs)>, DebNixError> {
+    read_outputs_where(location, |_| true)
+}
+
+/// [`read_outputs`], of the files that are accepted by the filter.
+fn read_outputs_where(
+    location: &Path,
+    filter: impl Fn(&Path) -> bool,
+) -> Result<Vec<(PathBuf, DebNixOutputs)>, DebNixError> {
     use std::io::Read;
-    let mut result: HashMap<String, String> = HashMap::new();
-    let outputs = Path::new("./outputs");
-    for output in outputs.read_dir()?.flatten() {
-        if output.file_type()?.is_file() {
+    let mut result = vec![];
+    let mut entries = location
+        .read_dir()
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", location.display())))?
+        .flatten()
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.path());
+    for output in entries {
+        // the journal of a sweep shares the output directory
+        if output.file_type()?.is_file()
+            && !Journal::is_journal(&output.path())
+            && filter(&output.path())
+        {
             let mut file = File::open(output.path())?;
             let mut contents = String::new();
             file.read_to_string(&mut contents)?;
             if let Ok(deserialized) = serde_json::from_str::<DebNixOutputs>(&contents) {
-                if let Some(deb_name) = deserialized.pkgs_name {
-                    if let Some(nix_name) = deserialized.nix_pkg {
-                        result.insert(deb_name.to_string(), nix_name.to_string());
-                    }
-                }
-                for key in deserialized.map.keys() {
-                    if let Some((_, values)) = deserialized.map.get_key_value(key) {
-                        result.insert(key.to_string(), values.to_string());
-                    }
-                }
+                result.push((output.path(), deserialized));
             } else {
                 error!("Reading: {:?}", output.path());
             }
         }
     }
-    // write the result map to the target location
-    let serialized = serde_json::to_string(&result)?;
-    let target_destination = "./outputs/maps/debnix.json";
-    let mut file = File::create(target_destination)
-        .map_err(|e| DebNixError::IoPath(format!("{e}: {target_destination}")))?;
+    Ok(result)
+}
+
+/// Writes a generated artifact as canonical json.
+fn write_artifact<T: Serialize>(destination: &Path, value: &T) -> Result<(), DebNixError> {
+    let serialized = to_canonical_json(value)?;
+    let mut file = File::create(destination)
+        .map_err(|e| DebNixError::IoPath(format!("{e}: {}", destination.display())))?;
     file.write_all(serialized.as_bytes())?;
     Ok(())
 }
 
-/// Open a json map from a specified location and read it.
-fn open_map(location: &str) -> Result<HashMap<String, String>, DebNixError> {
+/// The decimal places, that the floats of generated artifacts are rounded to.
+const FLOAT_PRECISION: i32 = 4;
+
+/// Serializes a generated artifact into canonical json:
+/// sorted keys, floats rounded to [`FLOAT_PRECISION`], two space indentation
+/// and a trailing newline, so that regenerating it produces minimal diffs.
+pub(crate) fn to_canonical_json<T: Serialize>(value: &T) -> Result<String, DebNixError> {
+    let mut value = serde_json::to_value(value)?;
+    round_floats(&mut value);
+    let mut serialized = serde_json::to_string_pretty(&value)?;
+    serialized.push('\n');
+    Ok(serialized)
+}
+
+/// Removes the noise of float arithmetic: `0.5599999999999999` -> `0.56`
+fn round_floats(value: &mut serde_json::Value) {
+    use serde_json::Value;
+
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            let scale = 10_f64.powi(FLOAT_PRECISION);
+            let rounded = number.as_f64().map(|float| (float * scale).round() / scale);
+            if let Some(rounded) = rounded.and_then(serde_json::Number::from_f64) {
+                *number = rounded;
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(round_floats),
+        Value::Object(map) => map.values_mut().for_each(round_floats),
+        _ => {}
+    }
+}
+
+/// Open a json map from a specified location and read it,
+/// either in the current, or in the former flat format.
+fn open_map(location: &str) -> Result<BTreeMap<String, Vec<NixTarget>>, DebNixError> {
     use std::io::Read;
 
     let mut file = File::open(location)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
-    Ok(serde_json::from_str::<HashMap<String, String>>(&contents)?)
+    let mut deserializer = serde_json::Deserializer::from_str(&contents);
+    Ok(deserialize_targets(&mut deserializer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(args: &[&str]) -> Result<State, DebNixError> {
+        use clap::Parser;
+
+        let args = std::iter::once("debnix").chain(args.iter().copied());
+        State::from_opts(CliArgs::try_parse_from(args).unwrap())
+    }
+
+    #[test]
+    fn sweeps_need_an_output_directory() {
+        for (args, flag) in [
+            (&["--discover", "10"][..], "--discover"),
+            (&["--discover-from", "list:pkgs.txt"], "--discover-from"),
+            (&["i3", "--recursive"], "--recursive"),
+            (&["--retry-failed"], "--retry-failed"),
+            (&["--resume"], "--resume"),
+            (&["--refresh"], "--refresh"),
+        ] {
+            let error = state(args).err().unwrap();
+            assert!(
+                matches!(&error, DebNixError::Config(message) if message.contains(&format!("`{flag}`"))),
+                "{error}"
+            );
+        }
+        let output = crate::testing::TempDir::new("state-output");
+        let output = output.path().display().to_string();
+        assert!(state(&["--discover", "10", "--output", &output]).is_ok());
+    }
+
+    fn outputs(json: &str) -> DebNixOutputs {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// A discovered pkg with one derivation, and one missing attribute.
+    fn discovered() -> DebNixOutputs {
+        outputs(
+            r#"{
+                "pkgs_name": "i3",
+                "nix_pkg": "i3",
+                "control_file_hash": "abc",
+                "nix_drvs": { "i3": "/nix/store/aaa-i3.drv" },
+                "nix_missing": ["i3-unwrapped"],
+                "deb_inputs": [],
+                "nix_inputs": [],
+                "map": {}
+            }"#,
+        )
+    }
+
+    fn lookups() -> Vec<String> {
+        vec!["i3-unwrapped".into(), "i3".into(), "i3".into()]
+    }
+
+    fn drv_path(attr: &str) -> Result<Option<String>, DebNixError> {
+        Ok((attr == "i3").then(|| "/nix/store/aaa-i3.drv".into()))
+    }
+
+    #[test]
+    fn unchanged_pkgs() {
+        let checksum = String::from("abc");
+        let changes = changes_of(&discovered(), Some(&checksum), lookups(), drv_path);
+        assert_eq!(changes.unwrap(), None);
+    }
+
+    #[test]
+    fn changed_control_files() {
+        let checksum = String::from("def");
+        let changes = changes_of(&discovered(), Some(&checksum), lookups(), |_| {
+            panic!("the derivations are not looked up")
+        });
+        assert_eq!(changes.unwrap().unwrap(), "control file changed");
+        let changes = changes_of(&discovered(), None, lookups(), drv_path);
+        assert_eq!(changes.unwrap().unwrap(), "control file changed");
+    }
+
+    #[test]
+    fn changed_lookups_and_derivations() {
+        let checksum = String::from("abc");
+        let changes = changes_of(&discovered(), Some(&checksum), vec!["i3".into()], drv_path);
+        assert_eq!(changes.unwrap().unwrap(), "nix lookups changed");
+        let changes = changes_of(&discovered(), Some(&checksum), lookups(), |attr| {
+            Ok((attr == "i3").then(|| "/nix/store/bbb-i3.drv".into()))
+        });
+        assert_eq!(changes.unwrap().unwrap(), "derivation of i3 changed");
+        let changes = changes_of(&discovered(), Some(&checksum), lookups(), |_| Ok(None));
+        assert_eq!(changes.unwrap().unwrap(), "derivation of i3 vanished");
+        let changes = changes_of(&discovered(), Some(&checksum), lookups(), |attr| {
+            Ok(Some(format!("/nix/store/aaa-{attr}.drv")))
+        });
+        assert_eq!(
+            changes.unwrap().unwrap(),
+            "derivation of i3-unwrapped appeared"
+        );
+    }
+
+    #[test]
+    fn outputs_without_recorded_derivations() {
+        let outputs = outputs(
+            r#"{
+                "pkgs_name": "i3",
+                "nix_pkg": null,
+                "control_file_hash": "abc",
+                "deb_inputs": [],
+                "nix_inputs": [],
+                "map": {}
+            }"#,
+        );
+        let checksum = String::from("abc");
+        let changes = changes_of(&outputs, Some(&checksum), lookups(), drv_path);
+        assert_eq!(changes.unwrap().unwrap(), "no recorded derivations");
+    }
+
+    #[test]
+    fn lookup_errors_are_returned() {
+        let checksum = String::from("abc");
+        let changes = changes_of(&discovered(), Some(&checksum), lookups(), |_| {
+            Err(DebNixError::Config("broken".into()))
+        });
+        assert!(matches!(changes, Err(DebNixError::Config(_))));
+    }
+
+    /// The discovered output of a pkg, that maps `libxcb1-dev`.
+    fn mapped(pkg: &str, attr: &str) -> String {
+        format!(
+            r#"{{
+                "pkgs_name": "{pkg}",
+                "nix_pkg": "{pkg}",
+                "control_file_hash": null,
+                "deb_inputs": ["libxcb1-dev"],
+                "nix_inputs": [],
+                "map": {{ "libxcb1-dev": [{{ "attr": "{attr}", "confidence": 1.0 }}] }}
+            }}"#
+        )
+    }
+
+    fn read_map(location: &Path) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(location).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn output_map_of_the_discovered_outputs() {
+        let output = crate::testing::TempDir::new("state-map");
+        output.write("i3-debnix.json", &mapped("i3", "xorg.libxcb"));
+        output.write("sway/sway-debnix.json", &mapped("sway", "xorg.libxcb"));
+        output.write("journal.json", "{}");
+        output.write("error/broken.json", &mapped("broken", "broken"));
+        output.write("maps/old-debnix.json", &mapped("old", "old"));
+        let inputs = [output.path().to_path_buf(), output.path().join("sway")];
+
+        // a directory location, with the default name
+        let maps = output.path().join("maps");
+        create_output_map(&inputs, &maps, None, ConflictPolicy::Majority, None).unwrap();
+        let map = read_map(&maps.join("debnix.json"));
+        assert_eq!(
+            map.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["i3", "libxcb1-dev", "sway"]
+        );
+        let flat = read_map(&maps.join("debnix-flat.json"));
+        assert_eq!(flat["libxcb1-dev"], "xorg.libxcb");
+        // the outputs of `sway` are read once, the votes agree
+        let conflicts = read_map(&maps.join("debnix-conflicts.json"));
+        assert!(conflicts["conflicts"].as_object().unwrap().is_empty());
+        assert!(maps.join("debnix-nixdeb.json").exists());
+
+        // a file location, the parents are created,
+        // the directory of the former map is an input now
+        std::fs::remove_dir_all(&maps).unwrap();
+        let location = output.path().join("generated/nested/all.json");
+        create_output_map(&inputs, &location, None, ConflictPolicy::Majority, None).unwrap();
+        for name in [
+            "all.json",
+            "all-flat.json",
+            "all-nixdeb.json",
+            "all-conflicts.json",
+        ] {
+            assert!(location.with_file_name(name).exists(), "{name}");
+        }
+        assert_eq!(read_map(&location), map);
+
+        // the artifacts of a map inside of an input are not read as outputs
+        let location = output.path().join("debnix.json");
+        output.write("debnix-conflicts.json", &mapped("stale", "stale"));
+        create_output_map(&inputs, &location, None, ConflictPolicy::Majority, None).unwrap();
+        create_output_map(&inputs, &location, None, ConflictPolicy::Majority, None).unwrap();
+        assert_eq!(read_map(&location), map);
+        let read = read_outputs_recursive(output.path(), Some(&location)).unwrap();
+        assert_eq!(
+            read.iter()
+                .map(|(path, _)| source_name(path))
+                .collect::<Vec<_>>(),
+            ["i3-debnix.json", "sway-debnix.json"]
+        );
+    }
+
+    #[test]
+    fn canonical_json_is_sorted_and_rounded() {
+        let value = std::collections::HashMap::from([
+            (
+                "b",
+                serde_json::json!({ "confidence": 0.7 * 0.8, "amount": 3 }),
+            ),
+            ("a", serde_json::json!([1.0 / 3.0, 0.9])),
+        ]);
+        assert_eq!(
+            to_canonical_json(&value).unwrap(),
+            r#"{
+  "a": [
+    0.3333,
+    0.9
+  ],
+  "b": {
+    "amount": 3,
+    "confidence": 0.56
+  }
+}
+"#
+        );
+    }
 }
@@ -0,0 +1,44 @@
+//! Fixtures, that are shared between the unit tests of the modules.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A temporary directory, that is removed once it is dropped.
+/// Every directory is unique, even if the name is reused across tests.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let location = std::env::temp_dir().join(format!(
+            "debnix-{}-{}-{name}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&location);
+        fs::create_dir_all(&location).unwrap();
+        Self(location)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes a file relative to the directory, its parents are created.
+    pub(crate) fn write(&self, name: impl AsRef<Path>, contents: &str) -> PathBuf {
+        let location = self.0.join(name);
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&location, contents).unwrap();
+        location
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}